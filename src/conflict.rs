@@ -0,0 +1,177 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use indexmap::IndexMap;
+use crate::{Error, Ini};
+
+/// What a file looked like when it was last read or written, to tell if something else has changed it since
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Fingerprint {
+    pub path: String,
+    /// None if the file didn't exist
+    state: Option<FileState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FileState {
+    len: u64,
+    hash: u64,
+}
+
+/// The files and values as they were when last read or saved, so changes made since can be told apart from changes made by others
+#[derive(Debug, Default)]
+pub(crate) struct Baseline {
+    /// The file and each file it includes, in the same order as the document
+    pub files: Vec<Fingerprint>,
+    pub map: IndexMap<String, IndexMap<String, Vec<String>>>,
+}
+
+impl Fingerprint {
+    /// Read a file along with its fingerprint. The metadata is taken first, so a change made while reading is caught later
+    pub fn read(path: &str) -> io::Result<(String, Fingerprint)> {
+        let metadata = fs::metadata(path)?;
+        let text = fs::read_to_string(path)?;
+        let state = FileState { len: metadata.len(), hash: hash(text.as_bytes()) };
+        Ok((text, Fingerprint { path: path.to_string(), state: Some(state) }))
+    }
+
+    /// The fingerprint of a file that doesn't exist
+    pub fn missing(path: &str) -> Fingerprint {
+        Fingerprint { path: path.to_string(), state: None }
+    }
+
+    /// The fingerprint of a file that was just written with the given text
+    pub fn written(path: &str, text: &str) -> io::Result<Fingerprint> {
+        let metadata = fs::metadata(path)?;
+        let state = FileState { len: metadata.len(), hash: hash(text.as_bytes()) };
+        Ok(Fingerprint { path: path.to_string(), state: Some(state) })
+    }
+
+    /// If the file held this text when it was last read or written
+    pub fn holds(&self, text: &str) -> bool {
+        self.state.as_ref().is_some_and(|x| x.len == text.len() as u64 && x.hash == hash(text.as_bytes()))
+    }
+
+    /// Check the file hasn't changed, returning Error::Conflict if it has.
+    /// A file of a different size has changed, otherwise its contents are compared, as an edit can keep the modified time.
+    pub fn check(&self) -> Result<(), Error> {
+        let unchanged = match (&self.state, fs::metadata(&self.path)) {
+            (None, Err(e)) if e.kind() == io::ErrorKind::NotFound => true,
+            (Some(_), Err(e)) if e.kind() == io::ErrorKind::NotFound => false,
+            (_, Err(e)) => return Err(e.into()),
+            (None, Ok(_)) => false,
+            (Some(state), Ok(metadata)) => metadata.len() == state.len && hash(&fs::read(&self.path)?) == state.hash,
+        };
+        if unchanged { Ok(()) } else { Err(Error::Conflict(self.path.clone())) }
+    }
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+impl Ini {
+    /// Read the file again and apply the changes made here since it was read or saved, for when save returned Error::Conflict.
+    /// Keys and sections that were set or removed here win, anything else takes the value now in the file.
+    /// This will not save the file.
+    pub fn reconcile(&mut self) -> Result<(), Error> {
+        if self.config_file.is_empty() {
+            return Err(Error::MissingPath);
+        }
+        let mut fresh = Ini::new_with_options(self.config_file.clone(), self.options.clone())?;
+        let base = std::mem::take(&mut self.baseline.get_mut().unwrap_or_else(|e| e.into_inner()).map);
+
+        for (section, keys) in &base {
+            match self.config_map.get(section) {
+                None => fresh.remove_section(section),
+                Some(now) => for key in keys.keys().filter(|k| !now.contains_key(*k)) {
+                    fresh.remove(section, key);
+                },
+            }
+        }
+        for (section, keys) in &self.config_map {
+            let before = base.get(section);
+            if before.is_none() {
+                fresh.insert_section(usize::MAX, section);
+            }
+            for (key, values) in keys {
+                if before.and_then(|s| s.get(key)) != Some(values) {
+                    fresh.set_all(section, key, &values.iter().map(String::as_str).collect::<Vec<&str>>());
+                }
+            }
+        }
+        *self = fresh;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::{Conflicts, Error, Ini, Options};
+
+    fn test_file(name: &str, text: Option<&str>) -> String {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("conflict-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        match text {
+            Some(x) => fs::write(&path, x).unwrap(),
+            None => { let _ = fs::remove_file(&path); },
+        }
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_conflict() {
+        let path = test_file("conflict.ini", Some("[a]\nkey = 1\nother = 1\n"));
+        let mut ini = Ini::new(path.clone()).unwrap();
+        ini.set("a", "key", "2");
+        ini.save().unwrap();
+        ini.set("a", "key", "3");
+        ini.save().unwrap();
+
+        fs::write(&path, "[a]\nkey = 3\nother = changed\n").unwrap();
+        ini.set("a", "key", "4");
+        assert!(matches!(ini.save(), Err(Error::Conflict(x)) if x == path));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[a]\nkey = 3\nother = changed\n");
+
+        ini.options.conflicts = Conflicts::Overwrite;
+        ini.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[a]\nkey = 4\nother = 1\n");
+
+        // An edit that keeps the size and modified time is still caught
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, "[a]\nkey = 5\nother = 1\n").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        ini.options.conflicts = Conflicts::Error;
+        ini.set("a", "key", "6");
+        assert!(matches!(ini.save(), Err(Error::Conflict(_))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[a]\nkey = 5\nother = 1\n");
+
+        let path = test_file("created.ini", None);
+        let mut ini = Ini::new(path.clone()).unwrap();
+        ini.set("a", "key", "1");
+        fs::write(&path, "[b]\nkey = 1\n").unwrap();
+        assert!(matches!(ini.save(), Err(Error::Conflict(_))));
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let path = test_file("reconcile.ini", Some("[a]\nkey = 1\nother = 1\ngone = 1\n[b]\nkey = 1\n"));
+        let mut ini = Ini::new_with_options(path.clone(), Options::default()).unwrap();
+        ini.set("a", "key", "mine");
+        ini.remove("a", "gone");
+        ini.remove_section("b");
+        ini.set("c", "key", "new");
+
+        fs::write(&path, "# edited\n[a]\nkey = 1\nother = theirs\ngone = 1\n[b]\nkey = 2\n[d]\nkey = theirs\n").unwrap();
+        assert!(matches!(ini.save(), Err(Error::Conflict(_))));
+        ini.reconcile().unwrap();
+        ini.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# edited\n[a]\nkey = mine\nother = theirs\n[d]\nkey = theirs\n[c]\nkey = new\n");
+    }
+}
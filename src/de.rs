@@ -0,0 +1,371 @@
+use std::fmt;
+use indexmap::IndexMap;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use crate::value::{invalid_value, parse_bool};
+use crate::{Error, Ini, SerdeError, GLOBAL_SECTION};
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Serde(SerdeError { section: None, key: None, message: msg.to_string() })
+    }
+}
+
+/// Deserialize a type from an INI string.
+/// Fields of the top level struct are sections, and their fields are the keys within.
+/// Global keys are also top level fields.
+pub fn from_str<T: DeserializeOwned>(str: &str) -> Result<T, Error> {
+    Ini::from_string(str.to_string())?.deserialize()
+}
+
+impl Ini {
+    /// Deserialize the INI data into a type.
+    /// Fields of the top level struct are sections, and their fields are the keys within.
+    /// Values are converted from strings as needed, missing keys can be Option or use #[serde(default)].
+    /// Keys with more than one value can be read into a Vec.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        T::deserialize(IniDeserializer { ini: self })
+    }
+}
+
+/// Deserializes the whole INI as a map of sections
+struct IniDeserializer<'a> {
+    ini: &'a Ini,
+}
+
+impl<'de> de::Deserializer<'de> for IniDeserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let globals = self.ini.config_map.get(GLOBAL_SECTION)
+            .map(|x| KeysAccess { ini: self.ini, section: GLOBAL_SECTION, keys: x.keys(), current: "" });
+        visitor.visit_map(SectionsAccess { ini: self.ini, globals, iter: self.ini.config_map.iter(), current: None })
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Global keys are given first as top level values, followed by the sections
+struct SectionsAccess<'a> {
+    ini: &'a Ini,
+    globals: Option<KeysAccess<'a>>,
+    iter: indexmap::map::Iter<'a, String, IndexMap<String, Vec<String>>>,
+    /// The current section, None while giving global keys
+    current: Option<&'a str>,
+}
+
+impl<'de> MapAccess<'de> for SectionsAccess<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        if let Some(globals) = &mut self.globals && globals.keys.len() > 0 {
+            self.current = None;
+            return globals.next_key_seed(seed);
+        }
+        match self.iter.find(|(section, _)| *section != GLOBAL_SECTION) {
+            Some((section, _)) => {
+                self.current = Some(section);
+                seed.deserialize(section.as_str().into_deserializer()).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match (self.current, &mut self.globals) {
+            (Some(section), _) => seed.deserialize(SectionDeserializer { ini: self.ini, section })
+                .map_err(|e| e.at(section, None)),
+            (None, Some(globals)) => globals.next_value_seed(seed),
+            (None, None) => Err(de::Error::custom("value requested before key")),
+        }
+    }
+}
+
+/// Deserializes a section as a map of keys
+struct SectionDeserializer<'a> {
+    ini: &'a Ini,
+    section: &'a str,
+}
+
+impl<'de> de::Deserializer<'de> for SectionDeserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let keys = self.ini.config_map[self.section].keys();
+        visitor.visit_map(KeysAccess { ini: self.ini, section: self.section, keys, current: "" })
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct KeysAccess<'a> {
+    ini: &'a Ini,
+    section: &'a str,
+    keys: indexmap::map::Keys<'a, String, Vec<String>>,
+    current: &'a str,
+}
+
+impl<'de> MapAccess<'de> for KeysAccess<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.keys.next() {
+            Some(key) => {
+                self.current = key;
+                seed.deserialize(key.as_str().into_deserializer()).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let values = self.ini.try_get_all(self.section, self.current)?;
+        seed.deserialize(ValueDeserializer::new(self.section, self.current, values))
+            .map_err(|e| e.at(self.section, Some(self.current)))
+    }
+}
+
+/// Deserializes a value, converting from its string form as needed.
+/// Keys with more than one value can be deserialized as a sequence, otherwise the last value is used.
+struct ValueDeserializer<'a> {
+    section: &'a str,
+    key: &'a str,
+    value: String,
+    values: Vec<String>,
+}
+
+impl<'a> ValueDeserializer<'a> {
+    fn new(section: &'a str, key: &'a str, values: Vec<String>) -> Self {
+        let value = values.last().cloned().unwrap_or_default();
+        ValueDeserializer { section, key, value, values }
+    }
+
+    fn invalid(&self, expected: &'static str, reason: String) -> Error {
+        invalid_value(self.section, self.key, &self.value, expected, reason)
+    }
+}
+
+/// Implement deserializing a number type by parsing the string value
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident: $ty:ty),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                match self.value.parse::<$ty>() {
+                    Ok(x) => visitor.$visit(x),
+                    Err(e) => Err(self.invalid(stringify!($ty), e.to_string())),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.value)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match parse_bool(&self.value) {
+            Some(x) => visitor.visit_bool(x),
+            None => Err(self.invalid("bool", "expected true/false, yes/no, on/off or 1/0".to_string())),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_i128 => visit_i128: i128,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_u128 => visit_u128: u128,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+        deserialize_char => visit_char: char
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_enum(self.value.into_deserializer())
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        // An empty value is an empty sequence, as that is how one is written
+        let values = if self.values.len() == 1 && self.value.is_empty() { Vec::new() } else { self.values };
+        visitor.visit_seq(ValuesAccess { section: self.section, key: self.key, values: values.into_iter() })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit_struct tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Gives each value of a key as an element of a sequence
+struct ValuesAccess<'a> {
+    section: &'a str,
+    key: &'a str,
+    values: std::vec::IntoIter<String>,
+}
+
+impl<'de> SeqAccess<'de> for ValuesAccess<'_> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        match self.values.next() {
+            Some(x) => seed.deserialize(ValueDeserializer::new(self.section, self.key, vec![x])).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use crate::{Error, Ini};
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "PascalCase")]
+    struct Config {
+        general: General,
+        database: Database,
+        #[serde(default)]
+        cache: Cache,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct General {
+        app_name: String,
+        enabled: bool,
+        level: Option<Level>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Level {
+        Debug,
+        Info,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Database {
+        host: String,
+        port: u16,
+        timeout: Option<f32>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq, Default)]
+    struct Cache {
+        size: u32,
+    }
+
+    #[test]
+    fn test_from_str() {
+        let config: Config = crate::from_str("[General]\napp_name = TestApp\nenabled = yes\nlevel = debug\n[Database]\nhost = localhost\nport = 5432\n").unwrap();
+        assert_eq!(config, Config {
+            general: General { app_name: "TestApp".to_string(), enabled: true, level: Some(Level::Debug) },
+            database: Database { host: "localhost".to_string(), port: 5432, timeout: None },
+            cache: Cache::default(),
+        });
+    }
+
+    #[test]
+    fn test_deserialize_ini() {
+        let ini = Ini::new("test.ini".to_string()).unwrap();
+        let config: Config = ini.deserialize().unwrap();
+        assert_eq!(config.database.port, 5432);
+        assert!(config.general.enabled);
+    }
+
+    #[test]
+    fn test_global_fields() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct EditorConfig {
+            root: bool,
+            #[serde(rename = "*")]
+            all: Style,
+        }
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Style {
+            indent_size: u8,
+        }
+        let config: EditorConfig = crate::from_str("root = true\n[*]\nindent_size = 4\n").unwrap();
+        assert_eq!(config, EditorConfig { root: true, all: Style { indent_size: 4 } });
+    }
+
+    #[test]
+    fn test_lists() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Plugins {
+            loader: Loader,
+        }
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Loader {
+            plugin: Vec<String>,
+            ports: Vec<u16>,
+            single: Vec<u8>,
+        }
+        let config: Plugins = crate::from_str("[loader]\nplugin[]=a\nplugin[]=b\nports[]=80\nports[]=443\nsingle=1\n").unwrap();
+        assert_eq!(config.loader, Loader { plugin: vec!["a".to_string(), "b".to_string()], ports: vec![80, 443], single: vec![1] });
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        let e = crate::from_str::<Config>("[General]\napp_name = a\nenabled = true\n[Database]\nhost = h\nport = lots\n").err().unwrap();
+        match &e {
+            Error::InvalidValue(v) => assert_eq!((v.section.as_str(), v.key.as_str(), v.expected), ("Database", "port", "u16")),
+            _ => panic!("expected invalid value, got {}", e),
+        }
+
+        let e = crate::from_str::<Config>("[General]\napp_name = a\nenabled = true\n[Database]\nport = 1\n").err().unwrap();
+        assert_eq!(e.to_string(), "[Database]: missing field `host`");
+
+        let e = crate::from_str::<Config>("[General]\napp_name = a\nenabled = true\nlevel = loud\n[Database]\nhost = h\nport = 1\n").err().unwrap();
+        assert_eq!(e.to_string(), "[General] level: unknown variant `loud`, expected `debug` or `info`");
+    }
+}
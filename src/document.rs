@@ -0,0 +1,489 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use indexmap::IndexMap;
+use std::ops::Range;
+use crate::quote::write_value;
+use crate::{DuplicateKeys, LineEnding, ListStyle, Options, CONFIG_KVP_SPLIT, CONFIG_PARENT_SPLIT, CONFIG_SECTION_END, CONFIG_SECTION_START, GLOBAL_SECTION, LIST_SUFFIX};
+
+/// The lines of an INI file exactly as they were read.
+/// Used when writing the file back out, so that only the lines that were actually changed are touched.
+#[derive(Clone, Debug)]
+pub(crate) struct Document {
+    pub lines: Vec<Line>,
+    /// The file each line was read from, 0 for the file itself and the others counting up through includes
+    pub files: Vec<usize>,
+    /// If the last line was terminated by a new line
+    pub trailing_newline: bool,
+    /// The new line the file was read with, None if it had none
+    pub new_line: Option<&'static str>,
+    /// Files read through include directives, file n is includes[n - 1]
+    pub includes: Vec<Included>,
+    /// The parent of each section as read, so only headers whose parent has changed are rewritten
+    pub parents: HashMap<String, String>,
+}
+
+/// A file read through an include directive
+#[derive(Clone, Debug)]
+pub(crate) struct Included {
+    pub path: String,
+    /// If the last line was terminated by a new line
+    pub trailing_newline: bool,
+    /// The new line the file was read with, None if it had none
+    pub new_line: Option<&'static str>,
+}
+
+/// A single line of an INI file
+#[derive(Clone, Debug)]
+pub(crate) enum Line {
+    /// Blank lines and comments, these are written back as they were read
+    Trivia(String),
+    /// A section header
+    Section { raw: String, name: String },
+    /// A key value pair
+    Entry(Entry),
+}
+
+/// A key value pair line, with the positions of the key and value within it
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub raw: String,
+    pub section: String,
+    pub key: String,
+    pub key_span: Range<usize>,
+    /// The value as it was read with any quotes and escapes removed, used to tell if it has been changed since
+    pub value: String,
+    /// Where the value is written in the line, including any quotes
+    pub value_span: Range<usize>,
+    /// Set when this line was ignored while reading because of a duplicate policy
+    pub shadowed: Shadowed,
+}
+
+/// Why a line was ignored while reading. Ignored lines are written back as they were, so reading the file again gives the same result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Shadowed {
+    No,
+    /// Another line for the same key won, this line is kept while the key exists
+    ByKey,
+    /// This line is in a repeated section that was ignored, it is kept while the section exists
+    BySection,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document { lines: Vec::new(), files: Vec::new(), trailing_newline: true, new_line: None, includes: Vec::new(), parents: HashMap::new() }
+    }
+}
+
+impl Entry {
+    /// Everything between the key and the value, e.g. ` = `
+    fn separator(&self) -> &str {
+        &self.raw[self.key_span.end..self.value_span.start]
+    }
+
+    /// A new line for another value of the same key, written the same way as this one
+    fn another_value(&self, value: &str, options: &Options) -> String {
+        let before = self.before_value();
+        format!("{}{}", before, write_value(value, before.len(), options))
+    }
+
+    /// The line up to where the value starts. If the value was empty or started on the next line, the spacing before `=` is copied after it
+    fn before_value(&self) -> Cow<'_, str> {
+        let before = &self.raw[..self.value_span.start];
+        let sep = self.separator();
+        if before.ends_with(CONFIG_KVP_SPLIT) && sep.len() > CONFIG_KVP_SPLIT.len() && sep.starts_with(' ') {
+            Cow::from(format!("{} ", before))
+        } else {
+            Cow::from(before)
+        }
+    }
+
+    /// The same line with the key marked as a list, `key[]`, so that another value after it isn't read as a duplicate
+    fn listed(&self) -> Cow<'_, Entry> {
+        if self.raw[self.key_span.clone()].ends_with(LIST_SUFFIX) {
+            return Cow::Borrowed(self);
+        }
+        let mut ret = self.clone();
+        ret.raw.insert_str(self.key_span.end, LIST_SUFFIX);
+        ret.key_span.end += LIST_SUFFIX.len();
+        ret.value_span = self.value_span.start + LIST_SUFFIX.len()..self.value_span.end + LIST_SUFFIX.len();
+        Cow::Owned(ret)
+    }
+
+    /// Rebuild the line with a new value, keeping the key and spacing as they were.
+    /// Any inline comment is kept unless InlineComments::preserve is turned off.
+    fn with_value(&self, value: &str, options: &Options) -> String {
+        let before = self.before_value();
+        let mut ret = String::with_capacity(self.raw.len() + value.len());
+        ret.push_str(&before);
+        ret.push_str(&write_value(value, before.len(), options));
+        if options.inline_comments.as_ref().is_none_or(|c| c.preserve) {
+            ret.push_str(&self.raw[self.value_span.end..]);
+        }
+        ret
+    }
+}
+
+impl Document {
+    /// Add a line read from the given file
+    pub fn push(&mut self, line: Line, file: usize) {
+        self.lines.push(line);
+        self.files.push(file);
+    }
+
+    /// If a key was read from the file
+    pub fn has_key(&self, section: &str, key: &str) -> bool {
+        self.lines.iter().any(|l| matches!(l, Line::Entry(e) if e.section == section && e.key == key))
+    }
+
+    /// Join lines of a file, which may themselves hold lines separated by \n, ending with a new line if the file did
+    fn join<S: AsRef<str>>(&self, file: usize, lines: &[S], line_ending: LineEnding) -> String {
+        if lines.is_empty() {
+            return String::new();
+        }
+        let (trailing_newline, read) = match file {
+            0 => (self.trailing_newline, self.new_line),
+            n => (self.includes[n - 1].trailing_newline, self.includes[n - 1].new_line),
+        };
+        let new_line = line_ending.resolve(read);
+        let mut ret = lines.iter().flat_map(|x| x.as_ref().split('\n')).collect::<Vec<_>>().join(new_line);
+        if trailing_newline {
+            ret.push_str(new_line);
+        }
+        ret
+    }
+
+    /// Write the document back out, applying any differences between it and the map.
+    /// Lines for keys and sections no longer in the map are dropped and changed values are rewritten in place.
+    /// Anything new is placed after whatever comes before it in the map, so keys and sections appended to the map end up at the end.
+    /// Returns the text of each file, the first being the file itself followed by any it includes.
+    pub fn render(&self, map: &IndexMap<String, IndexMap<String, Vec<String>>>, parents: &HashMap<String, String>, options: &Options) -> Vec<String> {
+        let separator = self.lines.iter().find_map(|l| match l {
+            Line::Entry(e) => Some(e.separator()),
+            _ => None,
+        }).unwrap_or(CONFIG_KVP_SPLIT);
+
+        // Find where each section and key is, so anything new can be put next to its neighbour
+        let owners = self.owners();
+        let mut first_header: HashMap<&str, usize> = HashMap::new();
+        let mut block_end: HashMap<&str, usize> = HashMap::new();
+        let mut key_lines: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+        let mut first_separator: HashMap<&str, &str> = HashMap::new();
+        let mut sections_start = self.lines.len();
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Some(s) = owners[i] {
+                block_end.insert(s, i + 1);
+            }
+            match line {
+                Line::Section { name, .. } => {
+                    first_header.entry(name).or_insert(i);
+                    sections_start = sections_start.min(i);
+                },
+                Line::Entry(e) if e.shadowed == Shadowed::No => {
+                    key_lines.entry((&e.section, &e.key)).or_default().push(i);
+                    first_separator.entry(&e.section).or_insert(e.separator());
+                },
+                _ => {},
+            }
+        }
+
+        // Global keys and sections with nothing before them go above the first section, and any comments attached to it
+        let mut global_start = sections_start;
+        while global_start > 0 && matches!(&self.lines[global_start - 1], Line::Trivia(raw) if !raw.trim().is_empty()) {
+            global_start -= 1;
+        }
+
+        // What each existing line of a key is written as
+        let mut plans: HashMap<usize, Vec<Cow<str>>> = HashMap::new();
+        for ((section, key), lines) in &key_lines {
+            let values = map.get(*section).and_then(|s| s.get(*key)).map_or(&[][..], |v| v.as_slice());
+            self.plan_key(lines, values, options, &mut plans);
+        }
+
+        // New lines, keyed by the index of the line they go before, with the file they go in.
+        // New keys go in the same file as the line before them, new sections always go in this file
+        let file_before = |at: usize| if at == 0 { 0 } else { self.files[at - 1] };
+        let mut inserts: HashMap<usize, Vec<(usize, String)>> = HashMap::new();
+        for (section, keys) in map {
+            let mut at = match first_header.get(section.as_str()) {
+                Some(header) => header + 1,
+                None if section == GLOBAL_SECTION => global_start,
+                None => continue,
+            };
+            let mut sep = first_separator.get(section.as_str()).copied().unwrap_or(separator);
+            for (k, values) in keys {
+                match key_lines.get(&(section.as_str(), k.as_str())) {
+                    Some(lines) => {
+                        let i = lines[lines.len() - 1];
+                        at = i + 1;
+                        if let Line::Entry(e) = &self.lines[i] {
+                            sep = e.separator();
+                        }
+                    },
+                    None => {
+                        let k = new_key_name(k, values, options);
+                        let file = file_before(at);
+                        inserts.entry(at).or_default().extend(values.iter().map(|v| (file, format!("{}{}{}", k, sep, write_value(v, k.len() + sep.len(), options)))));
+                    },
+                }
+            }
+        }
+        // New sections with none before them go above the sections still in the file, or at the end if there are none
+        let mut at = if block_end.keys().any(|s| map.contains_key(*s)) { global_start } else { self.lines.len() };
+        for (section, keys) in map {
+            if section == GLOBAL_SECTION {
+                continue;
+            }
+            if let Some(end) = block_end.get(section.as_str()) {
+                at = *end;
+                // Move past the rest of an included file
+                while at < self.lines.len() && file_before(at) != 0 && self.files[at] != 0 {
+                    at += 1;
+                }
+                continue;
+            }
+            let new = inserts.entry(at).or_default();
+            new.push((0, header(section, parents.get(section))));
+            for (k, values) in keys {
+                let k = new_key_name(k, values, options);
+                new.extend(values.iter().map(|v| (0, format!("{}{}{}", k, separator, write_value(v, k.len() + separator.len(), options)))));
+            }
+        }
+
+        let mut out: Vec<Vec<Cow<str>>> = vec![Vec::new(); self.includes.len() + 1];
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Some(new) = inserts.remove(&i) {
+                for (file, x) in new {
+                    out[file].push(Cow::from(x));
+                }
+            }
+            let out = &mut out[self.files[i]];
+            match line {
+                Line::Trivia(raw) => if owners[i].is_none_or(|s| map.contains_key(s)) { out.push(Cow::from(raw)) },
+                Line::Section { raw, name } => {
+                    if !map.contains_key(name) {
+                        continue;
+                    }
+                    // A changed parent is declared on the first header of the section only
+                    let parent = parents.get(name);
+                    if parent == self.parents.get(name) {
+                        out.push(Cow::from(raw));
+                    } else {
+                        out.push(Cow::from(header(name, parent.filter(|_| first_header[name.as_str()] == i))));
+                    }
+                },
+                Line::Entry(e) => {
+                    let values = map.get(&e.section).and_then(|s| s.get(&e.key));
+                    match e.shadowed {
+                        // Once the key is a list the ignored line would be read as one of its values
+                        Shadowed::ByKey => if values.is_some_and(|v| v.len() == 1) { out.push(Cow::from(&e.raw)) },
+                        Shadowed::BySection => if map.contains_key(&e.section) { out.push(Cow::from(&e.raw)) },
+                        Shadowed::No => if let Some(plan) = plans.remove(&i) { out.extend(plan) },
+                    }
+                },
+            }
+        }
+        if let Some(new) = inserts.remove(&self.lines.len()) {
+            for (file, x) in new {
+                out[file].push(Cow::from(x));
+            }
+        }
+
+        out.iter().enumerate().map(|(file, lines)| self.join(file, lines, options.line_ending)).collect()
+    }
+
+    /// Work out what each line of a key is written as.
+    /// Lines are matched up with the values they still hold, so adding or removing one value doesn't rewrite the others.
+    /// Lines without a value are dropped, and values without a line are added next to their neighbours.
+    fn plan_key<'a>(&'a self, lines: &[usize], values: &[String], options: &Options, plans: &mut HashMap<usize, Vec<Cow<'a, str>>>) {
+        let read = |i: usize| match &self.lines[i] {
+            Line::Entry(e) => e,
+            _ => unreachable!("key lines are always entries"),
+        };
+        // A key with more than one value is written as a list unless duplicates are all kept when read
+        let list = values.len() > 1 && as_list(options);
+        let entry = |i: usize| if list { read(i).listed() } else { Cow::Borrowed(read(i)) };
+        let raw = |i: usize| match entry(i) {
+            Cow::Borrowed(e) => Cow::from(&e.raw),
+            Cow::Owned(e) => Cow::from(e.raw),
+        };
+        let (n, m) = (lines.len(), values.len());
+        if n == m && lines.iter().zip(values).all(|(l, v)| read(*l).value == *v) {
+            for l in lines {
+                plans.insert(*l, vec![raw(*l)]);
+            }
+            return;
+        }
+
+        // Longest common subsequence of the values as read and the values now
+        let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if read(lines[i]).value == values[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+            }
+        }
+        let mut pairs: Vec<(usize, usize)> = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if read(lines[i]).value == values[j] {
+                pairs.push((i, j));
+                i += 1;
+                j += 1;
+            } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        pairs.push((n, m));
+
+        // Between each matched pair, unmatched lines take the unmatched values in order
+        let (mut i, mut j) = (0, 0);
+        for (pi, pj) in pairs {
+            let gap_lines = &lines[i..pi];
+            let gap_values = &values[j..pj];
+            for (line, v) in gap_lines.iter().zip(gap_values) {
+                plans.entry(*line).or_default().push(Cow::from(entry(*line).with_value(v, options)));
+            }
+            for line in gap_lines.iter().skip(gap_values.len()) {
+                plans.entry(*line).or_default();
+            }
+            if gap_values.len() > gap_lines.len() {
+                // Values left over go after the last line of the gap, the line before it, or before the first line
+                let (line, before) = match (gap_lines.last(), i.checked_sub(1)) {
+                    (Some(l), _) => (*l, false),
+                    (None, Some(p)) => (lines[p], false),
+                    (None, None) => (lines[0], true),
+                };
+                let e = entry(line);
+                let extra = gap_values[gap_lines.len()..].iter().map(|v| Cow::from(e.another_value(v, options)));
+                let plan = plans.entry(line).or_default();
+                if before {
+                    plan.splice(0..0, extra);
+                } else {
+                    plan.extend(extra);
+                }
+            }
+            if pi < n {
+                plans.entry(lines[pi]).or_default().push(raw(lines[pi]));
+            }
+            i = pi + 1;
+            j = pj + 1;
+        }
+    }
+
+    /// Reorder the lines so sections are in the same order as the map, and the keys within them are sorted by name.
+    /// Comments directly above a key or section move with it, the gaps between sections stay where they were.
+    pub fn sort(&mut self, map: &IndexMap<String, IndexMap<String, Vec<String>>>) {
+        let owners: Vec<Option<String>> = self.owners().into_iter().map(|o| o.map(String::from)).collect();
+        let lines = std::mem::take(&mut self.lines).into_iter().zip(std::mem::take(&mut self.files));
+        let mut lines = lines.zip(owners).peekable();
+
+        let mut preamble: Vec<(Line, usize)> = Vec::new();
+        while let Some((line, _)) = lines.next_if(|(_, o)| o.is_none()) {
+            preamble.push(line);
+        }
+        // Global keys are sorted too, anything above the last blank line before them stays at the top
+        let first_key = preamble.iter().position(|(l, _)| matches!(l, Line::Entry(_))).unwrap_or(preamble.len());
+        let head = preamble[..first_key].iter().rposition(|(l, _)| matches!(l, Line::Trivia(raw) if raw.trim().is_empty())).map_or(0, |i| i + 1);
+        let keys = preamble.split_off(head);
+        preamble.extend(Self::sort_block(keys));
+
+        // Split into blocks of lines belonging to the same section, taking the blank lines off the end of each
+        let mut blocks: Vec<(String, Vec<(Line, usize)>)> = Vec::new();
+        let mut gaps: Vec<Vec<(Line, usize)>> = Vec::new();
+        while let Some((line, owner)) = lines.next() {
+            let owner = owner.unwrap_or_default();
+            let mut block = vec![line];
+            while let Some((line, _)) = lines.next_if(|(_, o)| o.as_deref() == Some(owner.as_str())) {
+                block.push(line);
+            }
+            let mut gap: Vec<(Line, usize)> = Vec::new();
+            while matches!(block.last(), Some((Line::Trivia(raw), _)) if raw.trim().is_empty()) {
+                gap.insert(0, block.pop().unwrap());
+            }
+            blocks.push((owner, Self::sort_block(block)));
+            gaps.push(gap);
+        }
+        blocks.sort_by_key(|(name, _)| map.get_index_of(name));
+
+        let sorted = blocks.into_iter().zip(gaps).flat_map(|((_, block), gap)| block.into_iter().chain(gap));
+        (self.lines, self.files) = preamble.into_iter().chain(sorted).unzip();
+    }
+
+    /// Sort the entries in a block, each entry taking the comments directly above it along.
+    /// Lines are kept with the file they were read from
+    fn sort_block(block: Vec<(Line, usize)>) -> Vec<(Line, usize)> {
+        let mut head: Vec<(Line, usize)> = Vec::new();
+        let mut units: Vec<(String, Vec<(Line, usize)>)> = Vec::new();
+        let mut pending: Vec<(Line, usize)> = Vec::new();
+        for (line, file) in block {
+            match line {
+                Line::Entry(e) => {
+                    let key = e.key.clone();
+                    pending.push((Line::Entry(e), file));
+                    units.push((key, std::mem::take(&mut pending)));
+                },
+                Line::Section { .. } if units.is_empty() => {
+                    head.append(&mut pending);
+                    head.push((line, file));
+                },
+                _ => pending.push((line, file)),
+            }
+        }
+        units.sort_by(|a, b| a.0.cmp(&b.0));
+
+        head.extend(units.into_iter().flat_map(|(_, u)| u));
+        head.extend(pending);
+        head
+    }
+
+    /// Work out which section each line belongs to, so they can be dropped along with it.
+    /// Comments directly above a section header belong to that section, anything before the first header belongs to none.
+    /// Global keys are kept or dropped by their key alone, so the lines around them are always kept.
+    fn owners(&self) -> Vec<Option<&str>> {
+        let mut ret: Vec<Option<&str>> = Vec::with_capacity(self.lines.len());
+        let mut cur: Option<&str> = None;
+        for line in &self.lines {
+            match line {
+                Line::Section { name, .. } => {
+                    if cur.is_some() {
+                        for i in (0..ret.len()).rev() {
+                            match &self.lines[i] {
+                                Line::Trivia(raw) if !raw.trim().is_empty() => ret[i] = Some(name),
+                                _ => break,
+                            }
+                        }
+                    }
+                    cur = Some(name);
+                },
+                Line::Entry(e) if cur.is_some() => cur = Some(&e.section),
+                Line::Entry(_) => {},
+                Line::Trivia(_) => {},
+            }
+            ret.push(cur);
+        }
+        ret
+    }
+}
+
+/// The header for a section that isn't in the file yet, or whose parent has changed
+fn header(name: &str, parent: Option<&String>) -> String {
+    match parent {
+        Some(x) => format!("{}{} {} {}{}", CONFIG_SECTION_START, name, CONFIG_PARENT_SPLIT, x, CONFIG_SECTION_END),
+        None => format!("{}{}{}", CONFIG_SECTION_START, name, CONFIG_SECTION_END),
+    }
+}
+
+/// If keys with more than one value have to be written as `key[]` to be read back, which is any time DuplicateKeys::KeepAll isn't used
+fn as_list(options: &Options) -> bool {
+    options.duplicate_keys != DuplicateKeys::KeepAll
+}
+
+/// The key name to write for a key that isn't in the file yet, marking it as a list if it has more than one value and ListStyle::Brackets is used or needed
+fn new_key_name<'a>(key: &'a str, values: &[String], options: &Options) -> Cow<'a, str> {
+    if values.len() > 1 && (options.list_style == ListStyle::Brackets || as_list(options)) {
+        Cow::from(format!("{}{}", key, LIST_SUFFIX))
+    } else {
+        Cow::from(key)
+    }
+}
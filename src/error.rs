@@ -0,0 +1,293 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors returned while loading, building or saving INI data.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing the file failed
+    Io(io::Error),
+    /// The INI data could not be parsed
+    Syntax(SyntaxError),
+    /// save() was called without config_file being set
+    MissingPath,
+    /// The requested key doesn't exist
+    MissingKey { section: String, key: String },
+    /// The key exists but its value couldn't be converted
+    InvalidValue(ValueError),
+    /// Converting between INI data and a serde type failed
+    Serde(SerdeError),
+    /// A value refers to another that couldn't be expanded
+    Interpolation(InterpolationError),
+    /// The file lock couldn't be taken before Locking::timeout passed
+    LockTimeout,
+    /// save() found the file was changed by something else since it was read or saved, contains the path of the file
+    Conflict(String),
+    /// set_parent couldn't give the section that parent
+    Inheritance(InheritanceError),
+    /// A LayeredIni was created without any layers
+    NoLayers,
+    /// A key can't be written so that it reads back the same, such as one containing `=` or starting with `[`
+    InvalidKey { section: String, key: String },
+}
+
+/// A value that couldn't be converted to the requested type
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueError {
+    pub section: String,
+    pub key: String,
+    /// The value as it appears in the INI data
+    pub value: String,
+    /// Name of the type the value was being converted to
+    pub expected: &'static str,
+    /// Why the conversion failed
+    pub reason: String,
+}
+
+/// A value whose references to other values or environment variables couldn't be expanded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterpolationError {
+    /// Section of the value being read
+    pub section: String,
+    /// Key of the value being read
+    pub key: String,
+    /// The reference that failed as it was written, e.g. `${paths:root}`
+    pub reference: String,
+    pub kind: InterpolationErrorKind,
+}
+
+/// The reason a reference couldn't be expanded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InterpolationErrorKind {
+    /// The section or key being referred to doesn't exist
+    Missing,
+    /// The reference leads back to a value that is already being expanded
+    Cycle,
+    /// A reference was opened with `${` but never closed
+    Unterminated,
+    /// The environment variable being referred to isn't set, or is empty when using `${NAME:?message}`
+    MissingVariable,
+}
+
+/// A parent that couldn't be set for a section
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InheritanceError {
+    pub section: String,
+    /// The parent being set, None when removing it
+    pub parent: Option<String>,
+    pub kind: InheritanceErrorKind,
+}
+
+/// The reason a parent couldn't be set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InheritanceErrorKind {
+    /// Options::inheritance isn't set, so the header wouldn't be read back the same
+    Disabled,
+    /// Global keys can't inherit from a section
+    GlobalSection,
+    /// The parent doesn't exist, or comes after the section
+    MissingParent,
+    /// The parent inherits from the section, directly or through its parents
+    Cycle,
+}
+
+/// A serde conversion failure, with the section and key it happened at where known
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SerdeError {
+    pub section: Option<String>,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+/// Where, and why, INI data failed to parse
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// The file being read, None if the data came from a string
+    pub file: Option<String>,
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column of the offending text, starting at 1
+    pub column: usize,
+    /// The line that failed to parse
+    pub snippet: String,
+    pub kind: SyntaxErrorKind,
+}
+
+/// The reason a line failed to parse
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SyntaxErrorKind {
+    /// A section header was opened with `[` but never closed
+    UnterminatedSection,
+    /// The line isn't a section, key value pair or comment
+    InvalidLine,
+    /// A key was repeated within a section while using DuplicateKeys::Error
+    DuplicateKey,
+    /// A section header was repeated while using DuplicateSections::Error
+    DuplicateSection,
+    /// The file or directory named by an include directive couldn't be read
+    MissingInclude,
+    /// An include directive leads back to a file that is already being read
+    IncludeCycle,
+    /// Includes are nested deeper than Includes::max_depth
+    IncludeDepth,
+    /// A section inherits from a section that hasn't been declared before it
+    MissingParent,
+    /// A section inherits from itself, directly or through its parents
+    InheritanceCycle,
+}
+
+impl SyntaxError {
+    pub(crate) fn new(kind: SyntaxErrorKind, line: usize, snippet: &str) -> SyntaxError {
+        let column = snippet.len() - snippet.trim_start().len() + 1;
+        SyntaxError { file: None, line, column, snippet: snippet.to_string(), kind }
+    }
+}
+
+impl fmt::Display for SyntaxErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            SyntaxErrorKind::UnterminatedSection => "section header is missing a closing ]",
+            SyntaxErrorKind::InvalidLine => "line is not a section, key/value or comment",
+            SyntaxErrorKind::DuplicateKey => "key is already set in this section",
+            SyntaxErrorKind::DuplicateSection => "section has already been defined",
+            SyntaxErrorKind::MissingInclude => "included file or directory couldn't be read",
+            SyntaxErrorKind::IncludeCycle => "file includes itself",
+            SyntaxErrorKind::IncludeDepth => "includes are nested too deeply",
+            SyntaxErrorKind::MissingParent => "section inherits from a section that isn't declared before it",
+            SyntaxErrorKind::InheritanceCycle => "section inherits from itself",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}:{}: {}: {:?}", file, self.line, self.column, self.kind, self.snippet.trim()),
+            None => write!(f, "line {}, column {}: {}: {:?}", self.line, self.column, self.kind, self.snippet.trim()),
+        }
+    }
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid value {:?} for [{}] {}, expected {}: {}", self.value, self.section, self.key, self.expected, self.reason)
+    }
+}
+
+impl fmt::Display for InterpolationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self.kind {
+            InterpolationErrorKind::Missing => "refers to a key that doesn't exist",
+            InterpolationErrorKind::Cycle => "refers back to itself",
+            InterpolationErrorKind::Unterminated => "is missing a closing }",
+            InterpolationErrorKind::MissingVariable => "needs an environment variable that isn't set",
+        };
+        write!(f, "can't expand [{}] {}: {} {}", self.section, self.key, self.reference, reason)
+    }
+}
+
+impl fmt::Display for InheritanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self.kind {
+            InheritanceErrorKind::Disabled => "inheritance is not turned on",
+            InheritanceErrorKind::GlobalSection => "global keys can't have a parent",
+            InheritanceErrorKind::MissingParent => "the parent isn't declared before it",
+            InheritanceErrorKind::Cycle => "the section would inherit from itself",
+        };
+        match &self.parent {
+            Some(parent) => write!(f, "can't set the parent of [{}] to [{}]: {}", self.section, parent, reason),
+            None => write!(f, "can't remove the parent of [{}]: {}", self.section, reason),
+        }
+    }
+}
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.section, &self.key) {
+            (Some(section), Some(key)) => write!(f, "[{}] {}: {}", section, key, self.message),
+            (Some(section), None) => write!(f, "[{}]: {}", section, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Syntax(e) => write!(f, "{}", e),
+            Error::MissingPath => write!(f, "config_file is not set. This is likely because this was created using from_string()"),
+            Error::MissingKey { section, key } => write!(f, "key {} not found in section [{}]", key, section),
+            Error::InvalidValue(e) => write!(f, "{}", e),
+            Error::Serde(e) => write!(f, "{}", e),
+            Error::Interpolation(e) => write!(f, "{}", e),
+            Error::LockTimeout => write!(f, "timed out waiting for the file lock"),
+            Error::Conflict(path) => write!(f, "{} was changed by something else since it was read", path),
+            Error::Inheritance(e) => write!(f, "{}", e),
+            Error::NoLayers => write!(f, "a LayeredIni needs at least one layer"),
+            Error::InvalidKey { section, key } => write!(f, "key {:?} in section [{}] can't be written to an INI file", key, section),
+        }
+    }
+}
+
+impl error::Error for SyntaxError {}
+
+impl error::Error for ValueError {}
+
+impl error::Error for SerdeError {}
+
+impl error::Error for InterpolationError {}
+
+impl error::Error for InheritanceError {}
+
+/// Display already includes any error a variant holds, so it isn't given as the source too, which error reporters would print twice
+impl error::Error for Error {}
+
+impl Error {
+    /// Fill in where a serde error happened, if it isn't already known
+    #[cfg(feature = "serde")]
+    pub(crate) fn at(self, section: &str, key: Option<&str>) -> Error {
+        match self {
+            Error::Serde(mut e) => {
+                if e.section.is_none() {
+                    e.section = Some(section.to_string());
+                    e.key = key.map(String::from);
+                }
+                Error::Serde(e)
+            },
+            e => e,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<SyntaxError> for Error {
+    fn from(e: SyntaxError) -> Self {
+        Error::Syntax(e)
+    }
+}
+
+/// Lets callers that still work in io::Error use ? on this crate's results
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Syntax(_) => io::Error::new(io::ErrorKind::InvalidData, e),
+            Error::MissingPath | Error::MissingKey { .. } => io::Error::new(io::ErrorKind::NotFound, e),
+            Error::InvalidValue(_) | Error::Serde(_) | Error::Interpolation(_) => io::Error::new(io::ErrorKind::InvalidData, e),
+            Error::LockTimeout => io::Error::new(io::ErrorKind::TimedOut, e),
+            Error::Conflict(_) => io::Error::other(e),
+            Error::Inheritance(_) | Error::NoLayers | Error::InvalidKey { .. } => io::Error::new(io::ErrorKind::InvalidInput, e),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::document::{Line, Shadowed};
+use crate::Ini;
+
+/// Includes a single file, as in MySQL's `!include other.ini`
+const INCLUDE_FILE: &str = "!include";
+/// Includes every INI file in a directory in name order, as in MySQL's `!includedir conf.d/`
+const INCLUDE_DIR: &str = "!includedir";
+/// Files read by INCLUDE_DIR
+const INCLUDE_DIR_EXTENSIONS: [&str; 2] = ["ini", "cnf"];
+
+/// An include directive found on a line
+pub(crate) enum Directive<'a> {
+    File(&'a str),
+    Dir(&'a str),
+}
+
+/// Read an include directive from a trimmed line, returning None if it isn't one
+pub(crate) fn directive(trimmed: &str) -> Option<Directive<'_>> {
+    fn target(rest: &str) -> Option<&str> {
+        Some(rest).filter(|x| x.starts_with(char::is_whitespace)).map(str::trim)
+    }
+    if let Some(rest) = trimmed.strip_prefix(INCLUDE_DIR) {
+        target(rest).map(Directive::Dir)
+    } else if let Some(rest) = trimmed.strip_prefix(INCLUDE_FILE) {
+        target(rest).map(Directive::File)
+    } else {
+        None
+    }
+}
+
+/// The files a directive refers to. Relative paths are from the directory of the including file, or the current directory if there isn't one
+pub(crate) fn resolve(directive: &Directive, including: Option<&Path>) -> io::Result<Vec<PathBuf>> {
+    let target = match directive {
+        Directive::File(x) | Directive::Dir(x) => Path::new(x),
+    };
+    let path = match including.and_then(Path::parent) {
+        Some(dir) if target.is_relative() => dir.join(target),
+        _ => target.to_path_buf(),
+    };
+    match directive {
+        Directive::File(_) => {
+            // Fail now if it doesn't exist, rather than when it is read
+            fs::metadata(&path)?;
+            Ok(vec![path])
+        },
+        Directive::Dir(_) => {
+            let mut ret: Vec<PathBuf> = Vec::new();
+            for entry in fs::read_dir(&path)? {
+                let file = entry?.path();
+                if file.is_file() && file.extension().is_some_and(|x| INCLUDE_DIR_EXTENSIONS.iter().any(|e| x.eq_ignore_ascii_case(e))) {
+                    ret.push(file);
+                }
+            }
+            ret.sort();
+            Ok(ret)
+        },
+    }
+}
+
+impl Ini {
+    /// The file a key was read from, which may be one included by config_file.
+    /// Returns None if the key doesn't exist, was added since reading, or was read from a string.
+    pub fn source(&self, section: &str, key: &str) -> Option<&str> {
+        let (section, key, _) = self.lookup(section, key)?;
+        let i = self.document.lines.iter().rposition(|l| matches!(l, Line::Entry(e) if e.shadowed == Shadowed::No && &e.section == section && &e.key == key))?;
+        match self.document.files[i] {
+            0 => Some(self.config_file.as_str()).filter(|x| !x.is_empty()),
+            n => Some(self.document.includes[n - 1].path.as_str()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::{Error, Includes, Ini, Options, SyntaxErrorKind};
+
+    /// Write a set of files into a fresh directory under the target directory
+    fn write_files(name: &str, files: &[(&str, &str)]) -> String {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("include-tests").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        for (file, text) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        dir.to_string_lossy().to_string()
+    }
+
+    fn load(dir: &str) -> Result<Ini, Error> {
+        let options = Options { includes: Some(Includes::default()), ..Default::default() };
+        Ini::new_with_options(format!("{}/main.ini", dir), options)
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = write_files("include", &[
+            ("main.ini", "[app]\nname = main\n!include shared/base.ini\n!includedir conf.d\n[local]\nkey = 1\n"),
+            ("shared/base.ini", "[db]\nhost = localhost\nport = 5432\n"),
+            ("conf.d/10-db.ini", "[db]\nport = 6543\n"),
+            ("conf.d/20-app.cnf", "[app]\nname = override\n"),
+            ("conf.d/ignored.txt", "[app]\nname = ignored\n"),
+        ]);
+        let mut ini = load(&dir).unwrap();
+        assert_eq!(ini.get("db", "host").unwrap(), "localhost");
+        assert_eq!(ini.get("db", "port").unwrap(), "6543");
+        assert_eq!(ini.get("app", "name").unwrap(), "override");
+        assert_eq!(ini.get("local", "key").unwrap(), "1");
+
+        assert_eq!(ini.source("db", "host").unwrap(), format!("{}/shared/base.ini", dir));
+        assert_eq!(ini.source("db", "port").unwrap(), format!("{}/conf.d/10-db.ini", dir));
+        assert_eq!(ini.source("local", "key").unwrap(), format!("{}/main.ini", dir));
+        assert_eq!(ini.source("db", "missing"), None);
+
+        // Only the including file is written by to_string, and changes are saved to the file each key came from
+        assert_eq!(ini.to_string().unwrap(), "[app]\nname = main\n!include shared/base.ini\n!includedir conf.d\n[local]\nkey = 1\n");
+        ini.set("db", "host", "db.internal");
+        ini.set("db", "user", "admin");
+        ini.set("new", "key", "value");
+        ini.save().unwrap();
+        assert_eq!(fs::read_to_string(format!("{}/shared/base.ini", dir)).unwrap(), "[db]\nhost = db.internal\nport = 5432\n");
+        assert_eq!(fs::read_to_string(format!("{}/conf.d/10-db.ini", dir)).unwrap(), "[db]\nport = 6543\nuser = admin\n");
+        assert_eq!(fs::read_to_string(format!("{}/conf.d/20-app.cnf", dir)).unwrap(), "[app]\nname = override\n");
+        assert_eq!(fs::read_to_string(format!("{}/main.ini", dir)).unwrap(), "[app]\nname = main\n!include shared/base.ini\n!includedir conf.d\n[local]\nkey = 1\n[new]\nkey = value\n");
+    }
+
+    #[test]
+    fn test_include_keeps_section() {
+        let dir = write_files("section", &[
+            ("main.ini", "[app]\nname = main\n!include base.ini\nport = 80\n"),
+            ("base.ini", "[db]\nhost = localhost\n"),
+        ]);
+        let mut ini = load(&dir).unwrap();
+        assert_eq!(ini.get("app", "port").unwrap(), "80");
+        assert_eq!(ini.get("db", "port"), None);
+        assert_eq!(ini.source("app", "port").unwrap(), format!("{}/main.ini", dir));
+
+        ini.set("app", "port", "81");
+        ini.save().unwrap();
+        assert_eq!(fs::read_to_string(format!("{}/main.ini", dir)).unwrap(), "[app]\nname = main\n!include base.ini\nport = 81\n");
+        assert_eq!(fs::read_to_string(format!("{}/base.ini", dir)).unwrap(), "[db]\nhost = localhost\n");
+    }
+
+    #[test]
+    fn test_include_changed_back() {
+        let dir = write_files("changed-back", &[("main.ini", "!include base.ini\n"), ("base.ini", "[db]\nhost = localhost\n")]);
+        let mut ini = load(&dir).unwrap();
+        ini.set("db", "host", "a");
+        ini.save().unwrap();
+        ini.set("db", "host", "localhost");
+        ini.save().unwrap();
+        assert_eq!(fs::read_to_string(format!("{}/base.ini", dir)).unwrap(), "[db]\nhost = localhost\n");
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let kind = |dir: &str| match load(dir) {
+            Err(Error::Syntax(e)) => (e.kind, e.file.unwrap(), e.line),
+            x => panic!("expected a syntax error, got {:?}", x.map(|_| ())),
+        };
+
+        let dir = write_files("cycle", &[("main.ini", "!include a.ini\n"), ("a.ini", "[a]\n!include main.ini\n")]);
+        assert_eq!(kind(&dir), (SyntaxErrorKind::IncludeCycle, format!("{}/a.ini", dir), 2));
+
+        let dir = write_files("missing", &[("main.ini", "[a]\n!include nope.ini\n")]);
+        assert_eq!(kind(&dir), (SyntaxErrorKind::MissingInclude, format!("{}/main.ini", dir), 2));
+
+        let dir = write_files("depth", &[("main.ini", "!include a.ini\n"), ("a.ini", "!include b.ini\n"), ("b.ini", "[b]\nkey = 1\n")]);
+        let options = Options { includes: Some(Includes { max_depth: 1 }), ..Default::default() };
+        match Ini::new_with_options(format!("{}/main.ini", dir), options) {
+            Err(Error::Syntax(e)) => assert_eq!((e.kind, e.file.unwrap()), (SyntaxErrorKind::IncludeDepth, format!("{}/a.ini", dir))),
+            _ => panic!("expected a syntax error"),
+        }
+        assert_eq!(load(&dir).unwrap().get("b", "key").unwrap(), "1");
+
+        // Without the option, directives aren't recognised
+        assert!(matches!(Ini::new(format!("{}/main.ini", dir)), Err(Error::Syntax(e)) if e.kind == SyntaxErrorKind::InvalidLine));
+    }
+}
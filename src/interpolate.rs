@@ -0,0 +1,225 @@
+use crate::{find_name, Error, Ini, Interpolation, InterpolationError, InterpolationErrorKind};
+
+const REFERENCE_START: &str = "${";
+const REFERENCE_END: char = '}';
+const DOLLAR: char = '$';
+const PERCENT: char = '%';
+/// `${NAME:-default}` uses the default if the variable isn't set or is empty
+const VAR_DEFAULT: &str = ":-";
+/// `${NAME:?message}` is an error if the variable isn't set or is empty
+const VAR_REQUIRED: &str = ":?";
+/// Separates the section from the key in `${section:key}`
+const SECTION_SPLIT: char = ':';
+
+/// A value being expanded, so references back to it can be caught
+type Visiting<'a> = Vec<(&'a str, &'a str)>;
+
+impl Ini {
+    /// Get a value from the INI file exactly as it is written, without expanding any references.
+    /// If the key has more than one value, the last is returned.
+    pub fn get_raw(&self, section: &str, key: &str) -> Option<String> {
+        self.values(section, key)?.last().cloned()
+    }
+
+    /// Get a value from the INI file, expanding any references to other values.
+    /// Returns Error::MissingKey if the key doesn't exist, or Error::Interpolation if a reference can't be expanded.
+    pub fn try_get(&self, section: &str, key: &str) -> Result<String, Error> {
+        let value = self.get_raw(section, key).ok_or_else(|| Error::MissingKey { section: section.to_string(), key: key.to_string() })?;
+        self.expand(section, key, value)
+    }
+
+    /// Get every value of a key, in order, expanding any references to other values.
+    /// Returns an empty Vec if the key doesn't exist, or Error::Interpolation if a reference can't be expanded.
+    pub fn try_get_all(&self, section: &str, key: &str) -> Result<Vec<String>, Error> {
+        match self.values(section, key) {
+            Some(values) => values.iter().map(|v| self.expand(section, key, v.clone())).collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Expand the references in a value read from the given section and key
+    fn expand(&self, section: &str, key: &str, value: String) -> Result<String, Error> {
+        let enabled = self.options.interpolation != Interpolation::Off || self.options.environment.is_some();
+        if !enabled || !value.contains([DOLLAR, PERCENT]) {
+            return Ok(value);
+        }
+
+        self.expand_from(&value, section, &mut vec![(section, key)]).map_err(|(reference, kind)| {
+            Error::Interpolation(InterpolationError { section: section.to_string(), key: key.to_string(), reference, kind })
+        })
+    }
+
+    /// Expand a value, `visiting` holds the values being expanded so far, the last being the one this came from.
+    /// On failure returns the reference that failed and why.
+    fn expand_from<'a>(&'a self, value: &str, section: &'a str, visiting: &mut Visiting<'a>) -> Result<String, (String, InterpolationErrorKind)> {
+        let env = self.options.environment.as_ref();
+        let percent = env.is_some_and(|e| e.windows_style);
+        let mut ret = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(i) = rest.find(|c| c == DOLLAR || (percent && c == PERCENT)) {
+            ret.push_str(&rest[..i]);
+            rest = &rest[i..];
+
+            // %% is a literal %, and %NAME% is left as it is if the variable isn't set
+            if let Some(body) = rest.strip_prefix(PERCENT) {
+                let name = body.find(PERCENT).map(|end| &body[..end]);
+                let expanded = name.filter(|x| x.chars().all(is_var_char))
+                    .and_then(|x| if x.is_empty() { Some(PERCENT.to_string()) } else { env?.var(x) });
+                match expanded {
+                    Some(x) => {
+                        ret.push_str(&x);
+                        rest = &body[name.unwrap_or_default().len() + 1..];
+                    },
+                    None => {
+                        ret.push(PERCENT);
+                        rest = body;
+                    },
+                }
+                continue;
+            }
+
+            // $$ is a literal $, and a $ that doesn't start a reference is kept as it is
+            let Some(body) = rest.strip_prefix(REFERENCE_START) else {
+                ret.push(DOLLAR);
+                rest = rest.strip_prefix("$$").unwrap_or(&rest[1..]);
+                continue;
+            };
+            let Some(end) = body.find(REFERENCE_END) else {
+                return Err((rest.to_string(), InterpolationErrorKind::Unterminated));
+            };
+            let reference = &rest[..REFERENCE_START.len() + end + 1];
+            ret.push_str(&self.resolve(&body[..end], reference, section, visiting)?);
+            rest = &rest[reference.len()..];
+        }
+        ret.push_str(rest);
+        Ok(ret)
+    }
+
+    /// Find what the name in a `${...}` reference refers to, a key when using Options::interpolation or otherwise an environment variable
+    fn resolve<'a>(&'a self, name: &str, reference: &str, section: &'a str, visiting: &mut Visiting<'a>) -> Result<String, (String, InterpolationErrorKind)> {
+        let missing = |kind| (reference.to_string(), kind);
+        let env = self.options.environment.as_ref();
+        if let Some(env) = env {
+            if let Some((var, default)) = name.split_once(VAR_DEFAULT) {
+                return Ok(env.var(var).filter(|x| !x.is_empty()).unwrap_or_else(|| default.to_string()));
+            }
+            if let Some((var, _)) = name.split_once(VAR_REQUIRED) {
+                return env.var(var).filter(|x| !x.is_empty()).ok_or_else(|| missing(InterpolationErrorKind::MissingVariable));
+            }
+        }
+
+        if self.options.interpolation != Interpolation::Off {
+            let (ref_section, ref_key) = name.rsplit_once(SECTION_SPLIT).unwrap_or((section, name));
+            // Inherited values are expanded in the section they were asked for, so its own keys override its parents'
+            let found = find_name(&self.config_map, ref_section, self.options.case_insensitive)
+                .and_then(|(s, _)| self.lookup(s, ref_key).map(|(_, k, v)| (s.as_str(), k.as_str(), v.last())));
+            match found {
+                Some((ref_section, ref_key, Some(ref_value))) => {
+                    if visiting.contains(&(ref_section, ref_key)) {
+                        return Err(missing(InterpolationErrorKind::Cycle));
+                    }
+                    visiting.push((ref_section, ref_key));
+                    let ret = self.expand_from(ref_value, ref_section, visiting)?;
+                    visiting.pop();
+                    return Ok(ret);
+                },
+                // Fall back to the environment for names that could be a variable
+                _ if env.is_some() && !name.contains(SECTION_SPLIT) => {},
+                _ => return Err(missing(InterpolationErrorKind::Missing)),
+            }
+        }
+
+        env.and_then(|e| e.var(name)).ok_or_else(|| missing(InterpolationErrorKind::MissingVariable))
+    }
+}
+
+/// Characters allowed in a `%NAME%` variable name
+fn is_var_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '(' | ')')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::{Environment, Error, Ini, Interpolation, InterpolationErrorKind, Options};
+
+    const PATHS: &str = "root = /srv\n[paths]\nhome = ${:root}/app\nlogs = ${home}/logs\nprice = $$5 or $6\n[server]\nlog = ${paths:logs}/server.log\n";
+
+    fn load(text: &str) -> Ini {
+        let options = Options { interpolation: Interpolation::Extended, ..Default::default() };
+        Ini::from_string_with_options(text.to_string(), options).unwrap()
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let mut ini = load(PATHS);
+        assert_eq!(ini.get("paths", "logs").unwrap(), "/srv/app/logs");
+        assert_eq!(ini.get("server", "log").unwrap(), "/srv/app/logs/server.log");
+        assert_eq!(ini.get("paths", "price").unwrap(), "$5 or $6");
+        assert_eq!(ini.get_raw("server", "log").unwrap(), "${paths:logs}/server.log");
+
+        ini.set("paths", "home", "/opt");
+        assert_eq!(ini.get("server", "log").unwrap(), "/opt/logs/server.log");
+        assert!(ini.to_string().unwrap().contains("log = ${paths:logs}/server.log\n"));
+
+        ini.options.interpolation = Interpolation::Off;
+        assert_eq!(ini.get("paths", "logs").unwrap(), "${home}/logs");
+    }
+
+    #[test]
+    fn test_interpolation_errors() {
+        let ini = load("[a]\nmissing = ${nope}\nloop = ${b:loop}\nopen = ${a\nself = x ${self}\n[b]\nloop = ${a:loop}\n");
+        let kind = |key: &str| match ini.try_get("a", key) {
+            Err(Error::Interpolation(e)) => (e.kind, e.reference),
+            x => panic!("expected an interpolation error, got {:?}", x),
+        };
+        assert_eq!(kind("missing"), (InterpolationErrorKind::Missing, "${nope}".to_string()));
+        assert_eq!(kind("loop"), (InterpolationErrorKind::Cycle, "${a:loop}".to_string()));
+        assert_eq!(kind("open"), (InterpolationErrorKind::Unterminated, "${a".to_string()));
+        assert_eq!(kind("self"), (InterpolationErrorKind::Cycle, "${self}".to_string()));
+        assert_eq!(ini.get("a", "loop"), None);
+        assert_eq!(ini.try_get("a", "self").err().unwrap().to_string(), "can't expand [a] self: ${self} refers back to itself");
+    }
+
+    fn load_env(text: &str, interpolation: Interpolation) -> Ini {
+        let vars = HashMap::from([("DB_HOST".to_string(), "db.internal".to_string()), ("APPDATA".to_string(), r"C:\Users\me\AppData".to_string()), ("EMPTY".to_string(), String::new())]);
+        let environment = Environment { vars: Some(vars), ..Default::default() };
+        let options = Options { interpolation, environment: Some(environment), ..Default::default() };
+        Ini::from_string_with_options(text.to_string(), options).unwrap()
+    }
+
+    #[test]
+    fn test_environment() {
+        let text = "[db]\nhost = ${DB_HOST:-localhost}\nport = ${DB_PORT:-5432}\nuser = ${EMPTY:-admin}\ndir = %APPDATA%\\app\nrate = 50%% or 100%\nunset = %NOT_SET%\n";
+        let mut ini = load_env(text, Interpolation::Off);
+        assert_eq!(ini.get("db", "host").unwrap(), "db.internal");
+        assert_eq!(ini.get("db", "port").unwrap(), "5432");
+        assert_eq!(ini.get("db", "user").unwrap(), "admin");
+        assert_eq!(ini.get("db", "dir").unwrap(), r"C:\Users\me\AppData\app");
+        assert_eq!(ini.get("db", "rate").unwrap(), "50% or 100%");
+        assert_eq!(ini.get("db", "unset").unwrap(), "%NOT_SET%");
+        assert_eq!(ini.get_raw("db", "host").unwrap(), "${DB_HOST:-localhost}");
+
+        ini.set("db", "user", "root");
+        assert_eq!(ini.to_string().unwrap(), text.replace("${EMPTY:-admin}", "root"));
+
+        ini.options.environment.as_mut().unwrap().windows_style = false;
+        assert_eq!(ini.get("db", "dir").unwrap(), r"%APPDATA%\app");
+    }
+
+    #[test]
+    fn test_environment_errors() {
+        let ini = load_env("[db]\nhost = ${NOT_SET}\npassword = ${EMPTY:?password is required}\n", Interpolation::Off);
+        let e = ini.try_get("db", "password").err().unwrap();
+        assert_eq!(e.to_string(), "can't expand [db] password: ${EMPTY:?password is required} needs an environment variable that isn't set");
+        assert!(matches!(ini.try_get("db", "host"), Err(Error::Interpolation(e)) if e.kind == InterpolationErrorKind::MissingVariable));
+    }
+
+    #[test]
+    fn test_environment_with_interpolation() {
+        let ini = load_env("[db]\nDB_HOST = from key\nhost = ${DB_HOST}\nother = ${APPDATA}\nmissing = ${nope:key}\n", Interpolation::Extended);
+        assert_eq!(ini.get("db", "host").unwrap(), "from key");
+        assert_eq!(ini.get("db", "other").unwrap(), r"C:\Users\me\AppData");
+        assert!(matches!(ini.try_get("db", "missing"), Err(Error::Interpolation(e)) if e.kind == InterpolationErrorKind::Missing));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{same_name, Error, Ini, Options};
+
+/// Several INI files stacked on top of each other, such as system, user and local config.
+/// Values are read from the highest layer that has them, and changes are made to a single writable layer.
+pub struct LayeredIni {
+    /// The layers from lowest to highest precedence
+    pub layers: Vec<Ini>,
+    /// Index of the layer changed by set and remove, and written by save. Defaults to the highest.
+    /// These panic if it isn't the index of a layer
+    pub writable: usize,
+}
+
+impl LayeredIni {
+    /// Load INI files as layers, from lowest to highest precedence.
+    /// Files that don't exist are empty layers, so they are created if saved. Returns Error::NoLayers if no files are given.
+    pub fn new(locations: Vec<String>) -> Result<LayeredIni, Error> {
+        Self::new_with_options(locations, Options::default())
+    }
+
+    /// Load INI files as layers using the provided options, from lowest to highest precedence.
+    /// Files that don't exist are empty layers, so they are created if saved. Returns Error::NoLayers if no files are given.
+    pub fn new_with_options(locations: Vec<String>, options: Options) -> Result<LayeredIni, Error> {
+        let layers = locations.into_iter().map(|x| Ini::new_with_options(x, options.clone())).collect::<Result<Vec<Ini>, Error>>()?;
+        Self::from_layers(layers)
+    }
+
+    /// Stack already loaded INI data, from lowest to highest precedence.
+    /// Returns Error::NoLayers if the Vec is empty, as there would be no layer to write to.
+    pub fn from_layers(layers: Vec<Ini>) -> Result<LayeredIni, Error> {
+        if layers.is_empty() {
+            return Err(Error::NoLayers);
+        }
+        let writable = layers.len() - 1;
+        Ok(LayeredIni { layers, writable })
+    }
+
+    /// Index of the highest layer that has the key, which is the one get reads from.
+    /// Returns None if no layer has it.
+    pub fn layer_of(&self, section: &str, key: &str) -> Option<usize> {
+        self.layers.iter().rposition(|l| l.values(section, key).is_some())
+    }
+
+    /// Get a value from the highest layer that has the key.
+    pub fn get(&self, section: &str, key: &str) -> Option<String> {
+        self.layers[self.layer_of(section, key)?].get(section, key)
+    }
+
+    /// Get every value of a key from the highest layer that has it.
+    /// Returns an empty Vec if no layer has it.
+    pub fn get_all(&self, section: &str, key: &str) -> Vec<String> {
+        self.layer_of(section, key).map_or_else(Vec::new, |i| self.layers[i].get_all(section, key))
+    }
+
+    /// Names of the sections in any layer, in the order they first appear from the lowest layer up
+    pub fn sections(&self) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        for (layer, section) in self.layers.iter().flat_map(|l| l.config_map.keys().map(move |s| (l, s))) {
+            if !ret.iter().any(|x| same_name(x, section, layer.options.case_insensitive)) {
+                ret.push(section.clone());
+            }
+        }
+        ret
+    }
+
+    /// Set a value in the writable layer.
+    /// This will not save the file.
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        self.layers[self.writable].set(section, key, value);
+    }
+
+    /// Remove a key from the writable layer.
+    /// Lower layers that have the key are left alone, so get may still find it.
+    /// This will not save the file.
+    pub fn remove(&mut self, section: &str, key: &str) {
+        self.layers[self.writable].remove(section, key);
+    }
+
+    /// Save the writable layer, leaving the others untouched.
+    /// Ok will contain the size in bytes of the file after writing.
+    pub fn save(&self) -> Result<usize, Error> {
+        self.layers[self.writable].save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::{Error, Ini, LayeredIni};
+
+    fn layers() -> LayeredIni {
+        let system = Ini::from_string("[app]\ncolor = blue\nsize = 10\n[system]\nonly = yes\n".to_string()).unwrap();
+        let user = Ini::from_string("[app]\ncolor = green\n".to_string()).unwrap();
+        let local = Ini::from_string("[local]\nkey = 1\n".to_string()).unwrap();
+        LayeredIni::from_layers(vec![system, user, local]).unwrap()
+    }
+
+    #[test]
+    fn test_layered_get() {
+        let ini = layers();
+        assert_eq!(ini.get("app", "color").unwrap(), "green");
+        assert_eq!(ini.get("app", "size").unwrap(), "10");
+        assert_eq!(ini.get("local", "key").unwrap(), "1");
+        assert_eq!(ini.get("app", "missing"), None);
+        assert_eq!(ini.layer_of("app", "color"), Some(1));
+        assert_eq!(ini.layer_of("app", "size"), Some(0));
+        assert_eq!(ini.layer_of("app", "missing"), None);
+        assert_eq!(ini.sections(), ["app", "system", "local"]);
+    }
+
+    #[test]
+    fn test_layered_set() {
+        let mut ini = layers();
+        assert_eq!(ini.writable, 2);
+        ini.set("app", "size", "20");
+        assert_eq!(ini.get("app", "size").unwrap(), "20");
+        assert_eq!(ini.layer_of("app", "size"), Some(2));
+        assert_eq!(ini.layers[2].to_string().unwrap(), "[local]\nkey = 1\n[app]\nsize = 20\n");
+
+        ini.remove("app", "size");
+        assert_eq!(ini.get("app", "size").unwrap(), "10");
+
+        ini.writable = 1;
+        ini.set("app", "color", "red");
+        assert_eq!(ini.layers[1].to_string().unwrap(), "[app]\ncolor = red\n");
+        assert_eq!(ini.layers[0].get("app", "color").unwrap(), "blue");
+
+        assert!(matches!(LayeredIni::from_layers(Vec::new()), Err(Error::NoLayers)));
+        assert!(matches!(LayeredIni::new(Vec::new()), Err(Error::NoLayers)));
+    }
+
+    #[test]
+    fn test_layered_save() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("layered-tests");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let system = dir.join("system.ini").to_string_lossy().to_string();
+        let user = dir.join("user.ini").to_string_lossy().to_string();
+        fs::write(&system, "[app]\ncolor = blue\n").unwrap();
+
+        let mut ini = LayeredIni::new(vec![system.clone(), user.clone()]).unwrap();
+        assert_eq!(ini.get("app", "color").unwrap(), "blue");
+        ini.set("app", "color", "green");
+        ini.save().unwrap();
+        assert_eq!(fs::read_to_string(&system).unwrap(), "[app]\ncolor = blue\n");
+        assert_eq!(fs::read_to_string(&user).unwrap(), "[app]\ncolor=green\n");
+    }
+}
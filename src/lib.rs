@@ -1,169 +1,373 @@
-use std::fs::{OpenOptions};
+use std::fs::{self, OpenOptions};
 use std::io::{self, Write};
-use std::collections::BTreeMap;
-use std::env::consts::OS;
+use std::collections::HashMap;
 use std::fmt;
-use std::path::Path;
-extern crate read_lines_with_blank;
-use read_lines_with_blank::{read_lines_with_blank, read_lines_with_blank_from_str};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use indexmap::IndexMap;
 
-/// Load INI files into a structured BTreeMap, then edit them.
+mod conflict;
+mod document;
+mod error;
+mod include;
+mod interpolate;
+mod layered;
+mod lock;
+mod options;
+mod quote;
+mod reader;
+mod tree;
+mod value;
+#[cfg(feature = "serde")]
+mod de;
+#[cfg(feature = "serde")]
+mod ser;
+#[cfg(feature = "watch")]
+mod watch;
+use conflict::{Baseline, Fingerprint};
+use document::{Document, Entry, Included, Line, Shadowed};
+use include::Directive;
+pub use error::{Error, InheritanceError, InheritanceErrorKind, InterpolationError, InterpolationErrorKind, SerdeError, SyntaxError, SyntaxErrorKind, ValueError};
+pub use layered::LayeredIni;
+pub use options::{Conflicts, DuplicateKeys, DuplicateSections, Environment, Includes, InlineComments, Interpolation, LineEnding, ListStyle, Locking, Multiline, Options, SaveMode};
+pub use reader::{Event, EventKind, Reader};
+#[cfg(feature = "serde")]
+pub use de::from_str;
+#[cfg(feature = "serde")]
+pub use ser::to_string;
+#[cfg(feature = "watch")]
+pub use watch::{Change, WatchEvent, WatchOptions, Watcher};
+
+/// Load INI files into a structured IndexMap, then edit them.
 /// Can also create new INI files.
 /// You can access the data directly via config_map, or use the provided functions.
-/// This only works on Windows and Linux
+/// Sections and keys keep the order they were read in, anything new is added to the end.
+/// Keys before the first section are kept in GLOBAL_SECTION.
+/// Comments, blank lines and spacing are remembered, so saving only changes the lines that were edited.
+#[derive(Default)]
 pub struct Ini {
-    pub config_map: BTreeMap<String, BTreeMap<String, String>>,
+    /// Sections and keys in file order. Each key holds its values in the order they were read, usually just one.
+    /// When saving, anything already in the file stays where it is, and anything new is placed after whatever comes before it here.
+    pub config_map: IndexMap<String, IndexMap<String, Vec<String>>>,
     pub config_file: String,
+    /// The options the INI data was read with
+    pub options: Options,
+    document: Document,
+    /// The parent of each section declared with `[child : parent]`, when using Options::inheritance
+    parents: HashMap<String, String>,
+    /// The files and values as last read or saved. Saving updates it, so it is behind a lock
+    baseline: Mutex<Baseline>,
+}
+
+/// State kept while reading a file and the files it includes
+#[derive(Default)]
+struct Parser {
+    cur_sec: String,
+    /// The line holding the current value of each key, so it can be marked as shadowed if a later line wins
+    last_entry: HashMap<(String, String), usize>,
+    /// Set while reading a repeated section that DuplicateSections::FirstWins is ignoring
+    ignoring: bool,
+    /// The files being read, to catch includes that lead back to one of them
+    reading: Vec<PathBuf>,
+    /// How many includes deep the current file is
+    depth: usize,
 }
 
+/// Name of the section holding keys that appear before the first section header.
+/// These are written back first, before any section.
+pub const GLOBAL_SECTION: &str = "";
+
 const CONFIG_SECTION_START: &str = "[";
 const CONFIG_SECTION_END: &str = "]";
 const CONFIG_KVP_SPLIT: &str = "=";
+/// Separates a section from the section it inherits from, as in `[staging : production]`
+const CONFIG_PARENT_SPLIT: char = ':';
 const CONFIG_COMMENT_HASH: &str = "#";
 const CONFIG_COMMENT_SEMI: &str = ";";
+/// Marks a key as one value of a list, as in PHP's `key[]=value`
+const LIST_SUFFIX: &str = "[]";
 
-const NEW_LINE_WINDOWS: &str = "\r\n";
-const NEW_LINE_LINUX: &str = "\n";
+const NEW_LINE_CRLF: &str = "\r\n";
+const NEW_LINE_LF: &str = "\n";
 
 impl Ini {
     /// Load in an INI file and return its structure.
     /// If the file doesn't exist, then returns empty structure.
-    pub fn new(location: String) -> Result<Ini, io::Error> {
-        let mut ret = Ini{ config_map: BTreeMap::new(), config_file: location.clone() };
+    pub fn new(location: String) -> Result<Ini, Error> {
+        Self::new_with_options(location, Options::default())
+    }
+
+    /// Load in an INI file using the provided options and return its structure.
+    /// If the file doesn't exist, then returns empty structure.
+    /// If Options::locking is set, the file is locked while it is read.
+    pub fn new_with_options(location: String, options: Options) -> Result<Ini, Error> {
+        let _lock = match &options.locking {
+            Some(x) => lock::lock(&location, false, x)?,
+            None => None,
+        };
+        Self::load(location, options)
+    }
 
+    /// Read an INI file without locking it
+    fn load(location: String, options: Options) -> Result<Ini, Error> {
         if !Path::new(&location).exists() {
-            return Ok(ret);
+            let baseline = Baseline { files: vec![Fingerprint::missing(&location)], ..Default::default() };
+            return Ok(Ini { config_file: location, options, baseline: Mutex::new(baseline), ..Default::default() });
         }
-        
 
-        let lines = match read_lines_with_blank(&location) {
+        let (text, fingerprint) = Fingerprint::read(&location)?;
+        let mut ret = match Self::build_struct(&text, options, Some(Path::new(&location))) {
             Ok(x) => x,
-            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "Failed to read file"))
+            Err(Error::Syntax(mut e)) => {
+                e.file.get_or_insert(location);
+                return Err(Error::Syntax(e));
+            },
+            Err(e) => return Err(e),
         };
-
-        ret = match Self::build_struct(lines) {
-            Ok(x) => x,
-            Err(e) => return Err(e)
-        };
-
         ret.config_file = location;
+        // Included files were fingerprinted as they were read, this file goes before them
+        let baseline = ret.baseline.get_mut().unwrap_or_else(|e| e.into_inner());
+        baseline.files.insert(0, fingerprint);
+        baseline.map = ret.config_map.clone();
         Ok(ret)
     }
 
     /// Create ini structure from a string. Does not set the config_file so save doesn't work unless set manually.
-    pub fn from_string(str: String) -> Result<Ini, io::Error> {
-        let lines = match read_lines_with_blank_from_str(&str) {
-            Ok(x) => x,
-            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "Failed to read file"))
-        };
+    pub fn from_string(str: String) -> Result<Ini, Error> {
+        Self::from_string_with_options(str, Options::default())
+    }
 
-        Self::build_struct(lines)
+    /// Create ini structure from a string using the provided options. Does not set the config_file so save doesn't work unless set manually.
+    pub fn from_string_with_options(str: String, options: Options) -> Result<Ini, Error> {
+        Self::build_struct(&str, options, None)
     }
 
-    /// Build the struct given a set of lines
-    /// Will need the file location added
-    fn build_struct(lines: Vec<String>) -> Result<Ini, io::Error> {
-        let mut in_section = false;
-        let mut cur_sec: String = String::from("");
-        let mut ret = Ini{ config_map: BTreeMap::new(), config_file: "".to_string() };
+    /// Build the struct given the text of a file, and the file it was read from if any
+    fn build_struct(text: &str, options: Options, location: Option<&Path>) -> Result<Ini, Error> {
+        let mut ret = Ini { options, ..Default::default() };
+        let mut parser = Parser { cur_sec: GLOBAL_SECTION.to_string(), ..Default::default() };
+        if let Some(x) = location {
+            parser.reading.push(fs::canonicalize(x)?);
+        }
+        ret.read_lines(text, 0, location, &mut parser)?;
+        ret.document.parents = ret.parents.clone();
+        Ok(ret)
+    }
 
-        for line in lines {
-            if line.starts_with(CONFIG_COMMENT_HASH) || line.starts_with(CONFIG_COMMENT_SEMI) {
-                continue;
-            }
-            if line.len() == 0 {
-                continue;
-            }
+    /// Read the lines of a file into the struct, following any includes
+    fn read_lines(&mut self, text: &str, file: usize, location: Option<&Path>, parser: &mut Parser) -> Result<(), Error> {
+        let mut reader = Reader::new_with_options(text.as_bytes(), self.options.clone());
+        for event in reader.by_ref() {
+            let event = event?;
+            let n = event.line;
+            let line = event.raw;
+            match event.kind {
+                EventKind::Blank | EventKind::Comment => self.document.push(Line::Trivia(line), file),
+                EventKind::Include { path, dir } => {
+                    let directive = if dir { Directive::Dir(&path) } else { Directive::File(&path) };
+                    let paths = include::resolve(&directive, location)
+                        .map_err(|_| SyntaxError::new(SyntaxErrorKind::MissingInclude, n, &line))?;
+                    let snippet = line.clone();
+                    self.document.push(Line::Trivia(line), file);
+                    for path in paths {
+                        self.include(&path, parser).map_err(|kind| SyntaxError::new(kind, n, &snippet))??;
+                    }
+                },
+                EventKind::Section { name, parent } => {
+                    parser.cur_sec = self.section_name(&name);
+                    if let Some(parent) = parent {
+                        let parent = self.inherit(&parser.cur_sec, &parent).map_err(|kind| SyntaxError::new(kind, n, &line))?;
+                        self.parents.insert(parser.cur_sec.clone(), parent);
+                    }
 
-            // Section found
-            if line.starts_with(CONFIG_SECTION_START) && line.contains(CONFIG_SECTION_END) {
-                cur_sec = line.replace(CONFIG_SECTION_START, "").replace(CONFIG_SECTION_END, "").trim().to_string();
-                ret.config_map.insert(cur_sec.clone(), BTreeMap::new());
-                in_section = true;
-                continue;
+                    parser.ignoring = false;
+                    if self.config_map.contains_key(&parser.cur_sec) {
+                        match self.options.duplicate_sections {
+                            DuplicateSections::Error => return Err(SyntaxError::new(SyntaxErrorKind::DuplicateSection, n, &line).into()),
+                            DuplicateSections::FirstWins => parser.ignoring = true,
+                            DuplicateSections::LastWins => {
+                                for l in self.document.lines.iter_mut() {
+                                    if let Line::Entry(e) = l && e.section == parser.cur_sec {
+                                        e.shadowed = Shadowed::BySection;
+                                    }
+                                }
+                                self.config_map[&parser.cur_sec].clear();
+                            },
+                            DuplicateSections::Merge => {},
+                        }
+                    }
+                    self.config_map.entry(parser.cur_sec.clone()).or_default();
+                    self.document.push(Line::Section { raw: line, name: parser.cur_sec.clone() }, file);
+                },
+                EventKind::Entry { key, value, list } => {
+                    let (_, key) = self.names(&parser.cur_sec, &key);
+                    let mut shadowed = Shadowed::No;
+                    if parser.ignoring {
+                        // Keys only in the ignored section aren't added to the map
+                        shadowed = Shadowed::BySection;
+                    }
+                    else {
+                        let values = self.config_map.entry(parser.cur_sec.clone()).or_default().entry(key.clone()).or_default();
+                        if values.is_empty() || list {
+                            values.push(value.clone());
+                        }
+                        else {
+                            match self.options.duplicate_keys {
+                                DuplicateKeys::Error => return Err(SyntaxError::new(SyntaxErrorKind::DuplicateKey, n, &line).into()),
+                                DuplicateKeys::FirstWins => shadowed = Shadowed::ByKey,
+                                DuplicateKeys::LastWins => {
+                                    if let Some(i) = parser.last_entry.get(&(parser.cur_sec.clone(), key.clone()))
+                                        && let Line::Entry(e) = &mut self.document.lines[*i]
+                                        && e.shadowed == Shadowed::No {
+                                        e.shadowed = Shadowed::ByKey;
+                                    }
+                                    *values = vec![value.clone()];
+                                },
+                                DuplicateKeys::KeepAll => values.push(value.clone()),
+                            }
+                        }
+                    }
+                    if shadowed == Shadowed::No {
+                        parser.last_entry.insert((parser.cur_sec.clone(), key.clone()), self.document.lines.len());
+                    }
+                    self.document.push(Line::Entry(Entry {
+                        section: parser.cur_sec.clone(),
+                        key,
+                        key_span: event.key_span,
+                        value,
+                        value_span: event.value_span,
+                        shadowed,
+                        raw: line,
+                    }), file);
+                },
             }
-            // KVP found
-            else if line.contains(CONFIG_KVP_SPLIT) {
-                if !in_section {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Config file was invalid, KVP entry found before section."));
-                }
-
-                let kvp = match line.split_once(CONFIG_KVP_SPLIT) {
-                    Some(x) => x,
-                    None => return Err(io::Error::new(io::ErrorKind::InvalidData, "Config file was invalid, KVP entry couldn't be split.")),
-                };
-
-                ret.config_map.get_mut(&cur_sec).unwrap().insert(kvp.0.to_string(), kvp.1.to_string());
+        }
 
-                continue;
-            }
-            else {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "Config file was invalid, line didn't hit any requirement"));
-            }
+        match file {
+            0 => (self.document.trailing_newline, self.document.new_line) = (reader.trailing_newline, reader.new_line),
+            n => (self.document.includes[n - 1].trailing_newline, self.document.includes[n - 1].new_line) = (reader.trailing_newline, reader.new_line),
         }
-        Ok(ret)
+        Ok(())
     }
 
-    /// Dump out the INI file to a string, returns blank string if no data is present
-    pub fn to_string(&self) -> Result<String, io::Error> {
-        let new_line = match OS {
-            "linux" => NEW_LINE_LINUX,
-            "windows" => NEW_LINE_WINDOWS,
-            _ => return Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported OS"))
-        };
-
-        let mut ret: String = String::new();
-
-        if self.config_map.is_empty() { return Ok(ret) }
+    /// Read an included file into the struct.
+    /// Returns the reason if the include itself is a problem, otherwise the result of reading the file
+    fn include(&mut self, path: &Path, parser: &mut Parser) -> Result<Result<(), Error>, SyntaxErrorKind> {
+        let max_depth = self.options.includes.as_ref().map_or(0, |x| x.max_depth);
+        if parser.depth >= max_depth {
+            return Err(SyntaxErrorKind::IncludeDepth);
+        }
+        let canonical = fs::canonicalize(path).map_err(|_| SyntaxErrorKind::MissingInclude)?;
+        if parser.reading.contains(&canonical) {
+            return Err(SyntaxErrorKind::IncludeCycle);
+        }
 
-        for (section_k, section_v) in &self.config_map {
-            ret.push_str(CONFIG_SECTION_START);
-            ret.push_str(section_k);
-            ret.push_str(CONFIG_SECTION_END);
-            ret.push_str(new_line);
+        let location = path.to_string_lossy().to_string();
+        let text = match Fingerprint::read(&location) {
+            Ok((text, fingerprint)) => {
+                self.baseline.get_mut().unwrap_or_else(|e| e.into_inner()).files.push(fingerprint);
+                text
+            },
+            Err(e) => return Ok(Err(e.into())),
+        };
+        self.document.includes.push(Included { path: location.clone(), trailing_newline: true, new_line: None });
+        // Lines after the directive are still in the including file's section
+        let (cur_sec, ignoring) = (parser.cur_sec.clone(), parser.ignoring);
+        parser.reading.push(canonical);
+        parser.depth += 1;
+        let ret = self.read_lines(&text, self.document.includes.len(), Some(path), parser);
+        parser.depth -= 1;
+        parser.reading.pop();
+        (parser.cur_sec, parser.ignoring) = (cur_sec, ignoring);
+        Ok(ret.map_err(|e| match e {
+            Error::Syntax(mut e) => {
+                e.file.get_or_insert(location);
+                Error::Syntax(e)
+            },
+            e => e,
+        }))
+    }
 
-            for (k,v) in section_v {
-                ret.push_str(k);
-                ret.push_str(CONFIG_KVP_SPLIT);
-                ret.push_str(v);
-                ret.push_str(new_line);
+    /// Return Error::InvalidKey for a key that wouldn't be read back the same, such as one containing `=` or starting with `[`.
+    /// Keys read from the file are written back as they were, so only new ones are checked
+    fn check_keys(&self) -> Result<(), Error> {
+        for (section, keys) in &self.config_map {
+            for key in keys.keys() {
+                if !writable_key(key, &self.options) && !self.document.has_key(section, key) {
+                    return Err(Error::InvalidKey { section: section.clone(), key: key.clone() });
+                }
             }
         }
+        Ok(())
+    }
 
-        Ok(ret)
+    /// Write out the text of the file, followed by any files it includes
+    fn render(&self) -> Vec<String> {
+        self.document.render(&self.config_map, &self.parents, &self.options)
+    }
+
+    /// Dump out the INI file to a string, returns blank string if no data is present.
+    /// Comments and formatting from the loaded file are kept, only lines that were changed are rewritten.
+    /// Keys read from included files aren't part of this, save writes them back to their own files.
+    /// Lines are separated as set by Options::line_ending. Returns Error::InvalidKey if a key couldn't be read back, such as `[key`.
+    pub fn to_string(&self) -> Result<String, Error> {
+        self.check_keys()?;
+        Ok(self.render().swap_remove(0))
     }
 
     /// Save an INI file after being edited.
-    /// Only functions correctly on Windows and Linux.
     /// Ok will contain the size in bytes of the file after writing.
-    /// All comments in the INI file will be lost by doing this.
-    pub fn save(&self) -> Result<usize, io::Error> {
+    /// Comments and formatting in the INI file are kept.
+    /// Included files that had keys changed are saved too.
+    /// If Options::locking is set, the file is locked while it is written.
+    pub fn save(&self) -> Result<usize, Error> {
         if self.config_file.is_empty() {
-            return Err(io::Error::new(io::ErrorKind::Other, "config_file is not set. This is likely because this was created using from_string()"))
+            return Err(Error::MissingPath)
         }
-
-        let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.config_file)?;
-        let str = match self.to_string() {
-            Ok(x) => x,
-            Err(e) => return Err(e)
+        let _lock = match &self.options.locking {
+            Some(x) => lock::lock(&self.config_file, true, x)?,
+            None => None,
         };
+        self.write()
+    }
 
-        file.write_all(str.as_bytes())?;
-        file.flush()?;
-        file.sync_all()?;
-        
-        Ok(file.metadata()?.len() as usize)
+    /// Write the file and any changed includes without locking it
+    fn write(&self) -> Result<usize, Error> {
+        self.check_keys()?;
+        let mut baseline = self.baseline.lock().unwrap_or_else(|e| e.into_inner());
+        let files = self.render();
+        // Included files are only written if they would change from what was last read or written
+        let changed: Vec<usize> = (1..files.len()).filter(|n| !baseline.files[*n].holds(&files[*n])).collect();
+
+        // Saving to another path is a new file, so there is nothing to compare with
+        if self.options.conflicts == Conflicts::Error {
+            let main = baseline.files.first().filter(|x| x.path == self.config_file);
+            for fingerprint in main.into_iter().chain(changed.iter().map(|n| &baseline.files[*n])) {
+                fingerprint.check()?;
+            }
+        }
+
+        for n in changed {
+            let path = &self.document.includes[n - 1].path;
+            write_file(path, &files[n], self.options.save_mode)?;
+            baseline.files[n] = Fingerprint::written(path, &files[n])?;
+        }
+        let ret = write_file(&self.config_file, &files[0], self.options.save_mode)?;
+        let fingerprint = Fingerprint::written(&self.config_file, &files[0])?;
+        match baseline.files.first_mut() {
+            Some(x) => *x = fingerprint,
+            None => baseline.files.push(fingerprint),
+        }
+        baseline.map = self.config_map.clone();
+        Ok(ret)
     }
     
 
     /// Get a value from the INI file.
+    /// If the key has more than one value, the last is returned.
+    /// References to other values are expanded if Options::interpolation is set, returning None if they can't be. Use try_get to find out why.
     pub fn get(&self, section: &str, key: &str) -> Option<String> {
-        if let Some(section_map) = self.config_map.get(section) {
-            if let Some(value) = section_map.get(key) {
-                return Some(value.clone().trim_start().to_string());
-            }
-        }
-        None
+        self.try_get(section, key).ok()
     }
 
     /// Set a value in the INI file.
@@ -171,8 +375,50 @@ impl Ini {
     /// If the key doesn't exist, it will be created.
     /// This will not save the file.
     pub fn set(&mut self, section: &str, key: &str, value: &str) {
-        let section_map = self.config_map.entry(section.to_string()).or_insert(BTreeMap::new());
-        section_map.insert(key.to_string(), value.to_string());
+        let (section, key) = self.names(section, key);
+        let section_map = self.config_map.entry(section).or_default();
+        section_map.insert(key, vec![value.to_string()]);
+    }
+
+    /// Get every value of a key, in order.
+    /// Returns an empty Vec if the key doesn't exist, or any of its values can't be expanded. Use try_get_all to find out why.
+    pub fn get_all(&self, section: &str, key: &str) -> Vec<String> {
+        self.try_get_all(section, key).unwrap_or_default()
+    }
+
+    /// Add another value to a key, after any it already has.
+    /// If the section or key doesn't exist, it will be created.
+    /// This will not save the file.
+    pub fn add(&mut self, section: &str, key: &str, value: &str) {
+        let (section, key) = self.names(section, key);
+        let section_map = self.config_map.entry(section).or_default();
+        section_map.entry(key).or_default().push(value.to_string());
+    }
+
+    /// Set every value of a key, replacing any it already has.
+    /// Passing no values removes the key.
+    /// This will not save the file.
+    pub fn set_all(&mut self, section: &str, key: &str, values: &[&str]) {
+        if values.is_empty() {
+            self.remove(section, key);
+            return;
+        }
+        let (section, key) = self.names(section, key);
+        let section_map = self.config_map.entry(section).or_default();
+        section_map.insert(key, values.iter().map(|x| x.to_string()).collect());
+    }
+
+    /// Remove every occurrence of a value from a key, removing the key if it has no values left.
+    /// This will not save the file.
+    pub fn remove_value(&mut self, section: &str, key: &str, value: &str) {
+        let (section, key) = self.names(section, key);
+        if let Some(section_map) = self.config_map.get_mut(&section)
+            && let Some(values) = section_map.get_mut(&key) {
+            values.retain(|x| x != value);
+            if values.is_empty() {
+                section_map.shift_remove(&key);
+            }
+        }
     }
 
     /// Remove a key from the INI file.
@@ -180,31 +426,269 @@ impl Ini {
     /// If the key doesn't exist, it will be created.
     /// This will not save the file.
     pub fn remove(&mut self, section: &str, key: &str) {
-        if let Some(section_map) = self.config_map.get_mut(section) {
-            section_map.remove(key);
+        let (section, key) = self.names(section, key);
+        if let Some(section_map) = self.config_map.get_mut(&section) {
+            section_map.shift_remove(&key);
         }
     }
 
     /// Remove a section from the INI file.
+    /// Sections inheriting from it inherit from its parent instead, or from nothing if it had none.
     /// This will not save the file.
     pub fn remove_section(&mut self, section: &str) {
-        self.config_map.remove(section);
-    }   
+        let section = self.section_name(section);
+        self.config_map.shift_remove(&section);
+        self.unlink(&section);
+    }
+
+    /// Set a value at a position within its section.
+    /// If the section doesn't exist, it will be created at the end.
+    /// If the key already exists its value is updated and it stays where it is.
+    /// This will not save the file.
+    pub fn insert(&mut self, section: &str, index: usize, key: &str, value: &str) {
+        let (section, key) = self.names(section, key);
+        let section_map = self.config_map.entry(section).or_default();
+        match section_map.get_mut(&key) {
+            Some(x) => *x = vec![value.to_string()],
+            None => { section_map.shift_insert(index.min(section_map.len()), key, vec![value.to_string()]); },
+        }
+    }
+
+    /// Create an empty section at a position.
+    /// If the section already exists it stays where it is.
+    /// This will not save the file.
+    pub fn insert_section(&mut self, index: usize, section: &str) {
+        let section = self.section_name(section);
+        if !self.config_map.contains_key(&section) {
+            self.config_map.shift_insert(index.min(self.config_map.len()), section, IndexMap::new());
+        }
+    }
+
+    /// Sort the sections, and the keys within them, alphabetically.
+    /// A section inheriting from another is kept after its parent, so the file reads back the same.
+    /// Comments directly above a section or key move with it.
+    /// This will not save the file.
+    pub fn sort(&mut self) {
+        self.config_map.sort_keys();
+        if !self.parents.is_empty() {
+            let mut order: Vec<&String> = Vec::with_capacity(self.config_map.len());
+            for mut name in self.config_map.keys() {
+                // Place the parents that aren't placed yet first, starting from the top
+                let mut chain: Vec<&String> = Vec::new();
+                while !order.contains(&name) && !chain.contains(&name) {
+                    chain.push(name);
+                    match self.parents.get(name).and_then(|x| self.config_map.get_key_value(x)) {
+                        Some((x, _)) => name = x,
+                        None => break,
+                    }
+                }
+                order.extend(chain.into_iter().rev());
+            }
+            let position: HashMap<String, usize> = order.into_iter().enumerate().map(|(i, x)| (x.clone(), i)).collect();
+            self.config_map.sort_by(|a, _, b, _| position[a].cmp(&position[b]));
+        }
+        for section_map in self.config_map.values_mut() {
+            section_map.sort_keys();
+        }
+        self.document.sort(&self.config_map);
+    }
+
+    /// The section a section inherits keys from, declared with `[child : parent]` when using Options::inheritance
+    pub fn parent(&self, section: &str) -> Option<&str> {
+        self.parents.get(&self.section_name(section)).map(String::as_str)
+    }
+
+    /// Set the section a section inherits keys from, or stop it inheriting with None. The header is written as `[child : parent]`.
+    /// The parent must come before the section, which is created at the end if it doesn't exist.
+    /// Returns an error unless Options::inheritance is set, as the header wouldn't be read back the same without it.
+    /// This will not save the file.
+    pub fn set_parent(&mut self, section: &str, parent: Option<&str>) -> Result<(), Error> {
+        let section = self.section_name(section);
+        let error = |kind| Error::Inheritance(InheritanceError { section: section.clone(), parent: parent.map(String::from), kind });
+        if !self.options.inheritance {
+            return Err(error(InheritanceErrorKind::Disabled));
+        }
+        if section == GLOBAL_SECTION {
+            return Err(error(InheritanceErrorKind::GlobalSection));
+        }
+        let Some(parent) = parent else {
+            self.parents.remove(&section);
+            return Ok(());
+        };
+        let parent = match self.inherit(&section, parent) {
+            Ok(x) => x,
+            Err(SyntaxErrorKind::InheritanceCycle) => return Err(error(InheritanceErrorKind::Cycle)),
+            Err(_) => return Err(error(InheritanceErrorKind::MissingParent)),
+        };
+        if self.config_map.get_index_of(&section).is_some_and(|i| i < self.config_map.get_index_of(&parent).unwrap_or(0)) {
+            return Err(error(InheritanceErrorKind::MissingParent));
+        }
+        self.config_map.entry(section.clone()).or_default();
+        self.parents.insert(section, parent);
+        Ok(())
+    }
+
+    /// Forget the parent of a removed section, moving anything that inherited from it on to its parent
+    fn unlink(&mut self, section: &str) {
+        let parent = self.parents.remove(section);
+        self.parents.retain(|_, x| match &parent {
+            _ if x != section => true,
+            Some(p) => {
+                *x = p.clone();
+                true
+            },
+            None => false,
+        });
+    }
+
+    /// Check a section can inherit from a parent, returning the name the parent is stored under
+    fn inherit(&self, section: &str, parent: &str) -> Result<String, SyntaxErrorKind> {
+        let parent = self.section_name(parent);
+        if parent == GLOBAL_SECTION || !self.config_map.contains_key(&parent) {
+            return Err(SyntaxErrorKind::MissingParent);
+        }
+        let mut cur = Some(&parent);
+        while let Some(x) = cur {
+            if x == section {
+                return Err(SyntaxErrorKind::InheritanceCycle);
+            }
+            cur = self.parents.get(x);
+        }
+        Ok(parent)
+    }
+
+    /// The values of a key, matching names as set by Options::case_insensitive
+    pub(crate) fn values(&self, section: &str, key: &str) -> Option<&Vec<String>> {
+        self.lookup(section, key).map(|(_, _, v)| v)
+    }
+
+    /// Find a key, looking through the parents of the section if it doesn't have it.
+    /// Returns the names of the section and key it was found under along with its values
+    pub(crate) fn lookup(&self, section: &str, key: &str) -> Option<(&String, &String, &Vec<String>)> {
+        let case_insensitive = self.options.case_insensitive;
+        let (mut section, mut keys) = find_name(&self.config_map, section, case_insensitive)?;
+        loop {
+            if let Some((key, values)) = find_name(keys, key, case_insensitive) {
+                return Some((section, key, values));
+            }
+            (section, keys) = self.config_map.get_key_value(self.parents.get(section)?)?;
+        }
+    }
+
+    /// The names a section and key are stored under.
+    /// With Options::case_insensitive these are the spellings already in the map, otherwise they are used as given
+    fn names(&self, section: &str, key: &str) -> (String, String) {
+        let case_insensitive = self.options.case_insensitive;
+        match find_name(&self.config_map, section, case_insensitive) {
+            Some((section, keys)) => (section.clone(), find_name(keys, key, case_insensitive).map_or(key, |(k, _)| k).to_string()),
+            None => (section.to_string(), key.to_string()),
+        }
+    }
+
+    /// The name a section is stored under, see names
+    fn section_name(&self, section: &str) -> String {
+        find_name(&self.config_map, section, self.options.case_insensitive).map_or(section, |(s, _)| s).to_string()
+    }
+}
+
+/// Find a section or key in a map, ignoring case if asked to. An exact match is preferred
+pub(crate) fn find_name<'a, V>(map: &'a IndexMap<String, V>, name: &str, case_insensitive: bool) -> Option<(&'a String, &'a V)> {
+    match map.get_key_value(name) {
+        Some(x) => Some(x),
+        None if case_insensitive => map.iter().find(|(k, _)| same_name(k, name, true)),
+        None => None,
+    }
 }
 
-/// Display trait. Returns the string dump of INI data
+/// If two section or key names are the same, ignoring case if asked to
+pub(crate) fn same_name(a: &str, b: &str, case_insensitive: bool) -> bool {
+    a == b || (case_insensitive && a.chars().flat_map(char::to_lowercase).eq(b.chars().flat_map(char::to_lowercase)))
+}
+
+/// If a key written as `key=value` is read back as the same key, rather than as a section, comment, include or a different key
+fn writable_key(key: &str, options: &Options) -> bool {
+    let starts = [CONFIG_SECTION_START, CONFIG_COMMENT_HASH, CONFIG_COMMENT_SEMI].iter().any(|x| key.starts_with(x))
+        || options.inline_comments.as_ref().is_some_and(|c| c.starts_comment(key))
+        || (options.includes.is_some() && include::directive(&format!("{} ", key)).is_some());
+    !starts && key.trim() == key && !key.contains(CONFIG_KVP_SPLIT) && !key.contains(['\n', '\r']) && !key.ends_with(LIST_SUFFIX)
+}
+
+/// Write text to a file, returning its size in bytes after writing
+fn write_file(location: &str, text: &str, mode: SaveMode) -> Result<usize, Error> {
+    if mode == SaveMode::Atomic {
+        return write_atomic(location, text);
+    }
+    let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(location)?;
+
+    file.write_all(text.as_bytes())?;
+    file.flush()?;
+    file.sync_all()?;
+
+    Ok(file.metadata()?.len() as usize)
+}
+
+/// Write text to a temporary file next to the file, then rename it over the file
+fn write_atomic(location: &str, text: &str) -> Result<usize, Error> {
+    // Write through symlinks rather than replacing them
+    let target = fs::canonicalize(location).unwrap_or_else(|_| PathBuf::from(location));
+    let dir = match target.parent() {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => Path::new("."),
+    };
+    let name = target.file_name().map(|x| x.to_string_lossy()).unwrap_or_default();
+
+    let mut n = 0;
+    let (temp, file) = loop {
+        let temp = dir.join(format!(".{}.{}.{}.tmp", name, std::process::id(), n));
+        match OpenOptions::new().write(true).create_new(true).open(&temp) {
+            Ok(file) => break (temp, file),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    let len = match replace_with(file, &temp, &target, text) {
+        Ok(x) => x,
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            return Err(e.into());
+        },
+    };
+
+    // Make sure the rename itself is on disk. Directories can't be opened like this on Windows
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    Ok(len)
+}
+
+/// Write text to a new temporary file with the permissions of the target, then rename it over the target
+fn replace_with(mut file: fs::File, temp: &Path, target: &Path, text: &str) -> io::Result<usize> {
+    if let Ok(metadata) = fs::metadata(target) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.write_all(text.as_bytes())?;
+    file.flush()?;
+    file.sync_all()?;
+    let len = file.metadata()?.len() as usize;
+    drop(file);
+    fs::rename(temp, target)?;
+    Ok(len)
+}
+
+/// Display trait. Returns the string dump of INI data, without checking that keys can be read back as to_string does
 impl fmt::Display for Ini {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let ret = self.to_string().unwrap();
-        write!(f, "{}", ret)
+        write!(f, "{}", self.render().swap_remove(0))
     }
 }
 
 #[cfg(test)]
+// The original tests are kept as they were written
+#[allow(clippy::unnecessary_to_owned, clippy::len_zero, clippy::bool_assert_comparison)]
 mod tests {
     use std::fs::{self, File};
     use std::io::Read;
-    use crate::Ini;
+    use crate::{DuplicateKeys, DuplicateSections, Error, InheritanceErrorKind, InlineComments, Ini, Interpolation, LineEnding, ListStyle, Multiline, Options, SaveMode, SyntaxErrorKind, GLOBAL_SECTION};
 
     const INI: &str = "test.ini";
     const NEW_INI: &str = "test1.ini";
@@ -238,6 +722,13 @@ mod tests {
         assert_ne!(file.to_string().unwrap().len(), 0);
     }
 
+    #[test]
+    fn test_empty_string() {
+        let mut ini = Ini::from_string(String::new()).unwrap();
+        ini.set("a", "key", "1");
+        assert_eq!(ini.to_string().unwrap(), "[a]\nkey=1\n");
+    }
+
     #[test]
     fn test_save() {
         let mut file = Ini::new(INI.to_string()).unwrap();
@@ -268,4 +759,568 @@ mod tests {
         ini.remove("General", "app_name");
         assert_eq!(ini.get("General", "app_name"), None);
     }
-}
\ No newline at end of file
+
+    const COMMENTED: &str = "; Application settings\n[General]\napp_name = TestApp   ; shown in the title\n\n# Connection\n[Database]\nhost   =   localhost\nport=5432\n";
+
+    #[test]
+    fn test_round_trip_unchanged() {
+        let ini = Ini::from_string(COMMENTED.to_string()).unwrap();
+        assert_eq!(ini.to_string().unwrap(), COMMENTED);
+        assert_eq!(ini.get("Database", "host").unwrap(), "localhost");
+
+        let file = Ini::new(INI.to_string()).unwrap();
+        assert_eq!(file.to_string().unwrap(), get_text());
+    }
+
+    #[test]
+    fn test_edit_keeps_formatting() {
+        let mut ini = Ini::from_string(COMMENTED.to_string()).unwrap();
+        ini.set("Database", "host", "db.example.com");
+        ini.remove("Database", "port");
+        ini.set("General", "version", "2");
+        ini.set("Logging", "level", "DEBUG");
+        assert_eq!(
+            ini.to_string().unwrap(),
+            "; Application settings\n[General]\napp_name = TestApp   ; shown in the title\nversion = 2\n\n# Connection\n[Database]\nhost   =   db.example.com\n[Logging]\nlevel = DEBUG\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_section_keeps_others() {
+        let mut ini = Ini::from_string(COMMENTED.to_string()).unwrap();
+        ini.remove_section("General");
+        assert_eq!(ini.to_string().unwrap(), "; Application settings\n# Connection\n[Database]\nhost   =   localhost\nport=5432\n");
+    }
+
+    #[test]
+    fn test_keeps_order() {
+        let mut ini = Ini::new(INI.to_string()).unwrap();
+        let sections: Vec<&String> = ini.config_map.keys().collect();
+        assert_eq!(sections, ["General", "Database", "Logging", "Features"]);
+
+        ini.set("Aardvark", "zebra", "1");
+        ini.set("Aardvark", "apple", "2");
+        assert_eq!(ini.config_map.keys().last().unwrap(), "Aardvark");
+        assert!(ini.to_string().unwrap().ends_with("[Aardvark]\nzebra = 1\napple = 2\n"));
+    }
+
+    #[test]
+    fn test_insert_position() {
+        let mut ini = Ini::from_string(COMMENTED.to_string()).unwrap();
+        ini.insert("Database", 0, "driver", "postgres");
+        ini.insert_section(1, "Cache");
+        ini.set("Cache", "size", "10");
+        assert_eq!(
+            ini.to_string().unwrap(),
+            "; Application settings\n[General]\napp_name = TestApp   ; shown in the title\n\n[Cache]\nsize = 10\n# Connection\n[Database]\ndriver   =   postgres\nhost   =   localhost\nport=5432\n"
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut ini = Ini::from_string("[b]\n# about y\ny=1\nx=2\n\n[a]\nz=3\n".to_string()).unwrap();
+        ini.sort();
+        assert_eq!(ini.config_map.keys().collect::<Vec<&String>>(), ["a", "b"]);
+        assert_eq!(ini.to_string().unwrap(), "[a]\nz=3\n\n[b]\nx=2\n# about y\ny=1\n");
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let e = Ini::from_string("[General]\n[Broken\n".to_string()).err().unwrap();
+        match e {
+            Error::Syntax(e) => {
+                assert_eq!(e.kind, SyntaxErrorKind::UnterminatedSection);
+                assert_eq!((e.line, e.column), (2, 1));
+                assert_eq!(e.snippet, "[Broken");
+            },
+            _ => panic!("expected syntax error"),
+        }
+
+        let e = Ini::from_string("[General]\n  not a pair\n[Broken\n".to_string()).err().unwrap();
+        assert_eq!(e.to_string(), "line 2, column 3: line is not a section, key/value or comment: \"not a pair\"");
+    }
+
+    #[test]
+    fn test_error_printed_once() {
+        let e = Ini::from_string("[a\n".to_string()).err().unwrap();
+        assert!(std::error::Error::source(&e).is_none());
+        assert_eq!(e.to_string(), "line 1, column 1: section header is missing a closing ]: \"[a\"");
+    }
+
+    #[test]
+    fn test_syntax_error_has_file() {
+        const BAD_INI: &str = "test_bad.ini";
+        fs::write(BAD_INI, "[General]\nok=1\n[Broken\n").unwrap();
+        let e = Ini::new(BAD_INI.to_string()).err().unwrap();
+        _ = fs::remove_file(BAD_INI);
+        assert_eq!(e.to_string(), "test_bad.ini:3:1: section header is missing a closing ]: \"[Broken\"");
+    }
+
+    #[test]
+    fn test_save_without_path() {
+        let ini = Ini::from_string(COMMENTED.to_string()).unwrap();
+        assert!(matches!(ini.save(), Err(Error::MissingPath)));
+    }
+
+    #[test]
+    fn test_global_keys() {
+        let mut ini = Ini::from_string("; editorconfig\nroot = true\n\n[*]\nindent_style = space\n".to_string()).unwrap();
+        assert_eq!(ini.get(GLOBAL_SECTION, "root").unwrap(), "true");
+        assert_eq!(ini.config_map.keys().next().unwrap(), GLOBAL_SECTION);
+
+        ini.set(GLOBAL_SECTION, "charset", "utf-8");
+        assert_eq!(ini.to_string().unwrap(), "; editorconfig\nroot = true\ncharset = utf-8\n\n[*]\nindent_style = space\n");
+    }
+
+    #[test]
+    fn test_global_keys_written_first() {
+        let mut ini = Ini::from_string("# settings\n\n# about General\n[General]\nname=app\n".to_string()).unwrap();
+        ini.set(GLOBAL_SECTION, "version", "2");
+        assert_eq!(ini.to_string().unwrap(), "# settings\n\nversion=2\n# about General\n[General]\nname=app\n");
+
+        let mut ini = Ini::default();
+        ini.set("General", "name", "app");
+        ini.set(GLOBAL_SECTION, "version", "2");
+        assert_eq!(ini.to_string().unwrap(), "version=2\n[General]\nname=app\n");
+    }
+
+    #[test]
+    fn test_new_sections_appended() {
+        let mut ini = Ini::from_string("# My config\n".to_string()).unwrap();
+        ini.set("a", "k", "1");
+        assert_eq!(ini.to_string().unwrap(), "# My config\n[a]\nk=1\n");
+
+        let mut ini = Ini::from_string("a=1\n# trailing\n".to_string()).unwrap();
+        ini.set("s", "k", "1");
+        assert_eq!(ini.to_string().unwrap(), "a=1\n# trailing\n[s]\nk=1\n");
+    }
+
+    #[test]
+    fn test_sort_global_keys() {
+        let mut ini = Ini::from_string("# settings\n\nz=1\n# about a\na=2\n[s]\nk=1\n".to_string()).unwrap();
+        ini.sort();
+        assert_eq!(ini.to_string().unwrap(), "# settings\n\n# about a\na=2\nz=1\n[s]\nk=1\n");
+    }
+
+    const DUPLICATES: &str = "[a]\nx=1\nx=2\ny=3\n[b]\nz=4\n[a]\nx=5\nw=6\n";
+
+    fn with_duplicates(duplicate_keys: DuplicateKeys, duplicate_sections: DuplicateSections) -> Result<Ini, Error> {
+        Ini::from_string_with_options(DUPLICATES.to_string(), Options { duplicate_keys, duplicate_sections, ..Default::default() })
+    }
+
+    #[test]
+    fn test_duplicate_default_merges() {
+        let ini = Ini::from_string(DUPLICATES.to_string()).unwrap();
+        assert_eq!(ini.get("a", "x").unwrap(), "5");
+        assert_eq!(ini.get("a", "y").unwrap(), "3");
+        assert_eq!(ini.get("a", "w").unwrap(), "6");
+        assert_eq!(ini.to_string().unwrap(), DUPLICATES);
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let ini = with_duplicates(DuplicateKeys::FirstWins, DuplicateSections::Merge).unwrap();
+        assert_eq!(ini.get("a", "x").unwrap(), "1");
+
+        let ini = with_duplicates(DuplicateKeys::KeepAll, DuplicateSections::Merge).unwrap();
+        assert_eq!(ini.config_map["a"]["x"], ["1", "2", "5"]);
+        assert_eq!(ini.to_string().unwrap(), DUPLICATES);
+
+        let ini = with_duplicates(DuplicateKeys::LastWins, DuplicateSections::FirstWins).unwrap();
+        assert_eq!(ini.get("a", "x").unwrap(), "2");
+        assert_eq!(ini.get("a", "w"), None);
+        assert!(!ini.config_map["a"].contains_key("w"));
+        assert_eq!(ini.to_string().unwrap(), DUPLICATES);
+
+        let ini = Ini::from_string_with_options("[a]\nx=1\n[a]\nw=6\n".to_string(), Options { duplicate_sections: DuplicateSections::FirstWins, ..Default::default() }).unwrap();
+        assert!(!ini.config_map["a"].contains_key("w"));
+        assert_eq!(ini.get_all("a", "w"), Vec::<String>::new());
+        assert_eq!(ini.to_string().unwrap(), "[a]\nx=1\n[a]\nw=6\n");
+
+        let ini = with_duplicates(DuplicateKeys::LastWins, DuplicateSections::LastWins).unwrap();
+        assert_eq!(ini.get("a", "x").unwrap(), "5");
+        assert_eq!(ini.get("a", "y"), None);
+        assert_eq!(ini.to_string().unwrap(), DUPLICATES);
+    }
+
+    #[test]
+    fn test_duplicate_errors() {
+        match with_duplicates(DuplicateKeys::Error, DuplicateSections::Merge) {
+            Err(Error::Syntax(e)) => assert_eq!((e.kind, e.line), (SyntaxErrorKind::DuplicateKey, 3)),
+            _ => panic!("expected duplicate key error"),
+        }
+        match with_duplicates(DuplicateKeys::KeepAll, DuplicateSections::Error) {
+            Err(Error::Syntax(e)) => assert_eq!((e.kind, e.line), (SyntaxErrorKind::DuplicateSection, 7)),
+            _ => panic!("expected duplicate section error"),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_edits() {
+        let mut ini = Ini::from_string(DUPLICATES.to_string()).unwrap();
+        ini.remove("a", "x");
+        assert_eq!(ini.to_string().unwrap(), "[a]\ny=3\n[b]\nz=4\n[a]\nw=6\n");
+
+        let mut ini = with_duplicates(DuplicateKeys::KeepAll, DuplicateSections::Merge).unwrap();
+        ini.set("a", "x", "9");
+        assert_eq!(ini.to_string().unwrap(), "[a]\nx=9\ny=3\n[b]\nz=4\n[a]\nw=6\n");
+    }
+
+    const PLUGINS: &str = "[loader]\nplugin = a\n; the important one\nplugin = b\nplugin = c\n[php]\nextension[] = gd\nextension[] = curl\n";
+
+    #[test]
+    fn test_multiple_values() {
+        let keep_all = Options { duplicate_keys: DuplicateKeys::KeepAll, ..Default::default() };
+        let mut ini = Ini::from_string_with_options(PLUGINS.to_string(), keep_all).unwrap();
+        assert_eq!(ini.get_all("loader", "plugin"), ["a", "b", "c"]);
+        assert_eq!(ini.get("loader", "plugin").unwrap(), "c");
+        assert_eq!(ini.get_all("php", "extension"), ["gd", "curl"]);
+        assert!(ini.get_all("php", "missing").is_empty());
+        assert_eq!(ini.to_string().unwrap(), PLUGINS);
+
+        ini.remove_value("loader", "plugin", "a");
+        ini.add("loader", "plugin", "d");
+        ini.add("php", "extension", "zip");
+        assert_eq!(
+            ini.to_string().unwrap(),
+            "[loader]\n; the important one\nplugin = b\nplugin = c\nplugin = d\n[php]\nextension[] = gd\nextension[] = curl\nextension[] = zip\n"
+        );
+
+        ini.set_all("loader", "plugin", &["b", "x", "c"]);
+        assert!(ini.to_string().unwrap().starts_with("[loader]\n; the important one\nplugin = b\nplugin = x\nplugin = c\n[php]"));
+    }
+
+    #[test]
+    fn test_list_brackets_always_kept() {
+        let ini = Ini::from_string(PLUGINS.to_string()).unwrap();
+        assert_eq!(ini.get_all("loader", "plugin"), ["c"]);
+        assert_eq!(ini.get_all("php", "extension"), ["gd", "curl"]);
+    }
+
+    #[test]
+    fn test_new_list_style() {
+        let mut ini = Ini::default();
+        ini.set_all("loader", "plugin", &["a", "b"]);
+        ini.set_all("loader", "single", &["a"]);
+        assert_eq!(ini.to_string().unwrap(), "[loader]\nplugin[]=a\nplugin[]=b\nsingle=a\n");
+
+        ini.options.duplicate_keys = DuplicateKeys::KeepAll;
+        assert_eq!(ini.to_string().unwrap(), "[loader]\nplugin=a\nplugin=b\nsingle=a\n");
+
+        ini.options.list_style = ListStyle::Brackets;
+        assert_eq!(ini.to_string().unwrap(), "[loader]\nplugin[]=a\nplugin[]=b\nsingle=a\n");
+    }
+
+    #[test]
+    fn test_lists_round_trip() {
+        let mut ini = Ini::from_string("[s]\np = a\n".to_string()).unwrap();
+        ini.add("s", "p", "b");
+        let text = ini.to_string().unwrap();
+        assert_eq!(text, "[s]\np[] = a\np[] = b\n");
+        assert_eq!(Ini::from_string(text).unwrap().get_all("s", "p"), ["a", "b"]);
+
+        for duplicate_keys in [DuplicateKeys::LastWins, DuplicateKeys::FirstWins] {
+            let options = Options { duplicate_keys, ..Default::default() };
+            let mut ini = Ini::from_string_with_options("[s]\np=a\np=b\n".to_string(), options.clone()).unwrap();
+            ini.set_all("s", "p", &["x", "y"]);
+            let text = ini.to_string().unwrap();
+            assert_eq!(Ini::from_string_with_options(text, options).unwrap().get_all("s", "p"), ["x", "y"]);
+        }
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        for key in ["[k", "#k", ";k", "a=b", "two\nlines", " k", "k[]"] {
+            let mut ini = Ini::default();
+            ini.set("s", key, "v");
+            assert!(matches!(ini.to_string(), Err(Error::InvalidKey { key: k, .. }) if k == key), "{:?}", key);
+        }
+
+        let mut ini = Ini::default();
+        ini.set(GLOBAL_SECTION, "a]b", "1");
+        ini.set("s", "a b.c;d#e", "2");
+        let read = Ini::from_string(ini.to_string().unwrap()).unwrap();
+        assert_eq!(read.get(GLOBAL_SECTION, "a]b").unwrap(), "1");
+        assert_eq!(read.get("s", "a b.c;d#e").unwrap(), "2");
+
+        // Keys read from the file are written back as they were
+        let options = Options { inline_comments: Some(InlineComments { markers: vec!["//".to_string()], ..Default::default() }), ..Default::default() };
+        let mut ini = Ini::from_string_with_options("[s]\n//k = 1\n".to_string(), options).unwrap();
+        assert_eq!(ini.to_string().unwrap(), "[s]\n//k = 1\n");
+        ini.set("s", "//new", "2");
+        assert!(matches!(ini.to_string(), Err(Error::InvalidKey { .. })));
+    }
+
+    #[test]
+    fn test_quoted_values() {
+        let text = "[quoted]\ndouble = \"  a \\\"b\\\" \\n c  \"\nsingle = 'it\\'s'\nplain = C:\\path\\file\n";
+        let mut ini = Ini::from_string(text.to_string()).unwrap();
+        assert_eq!(ini.get("quoted", "double").unwrap(), "  a \"b\" \n c  ");
+        assert_eq!(ini.get("quoted", "single").unwrap(), "it's");
+        assert_eq!(ini.get("quoted", "plain").unwrap(), "C:\\path\\file");
+        assert_eq!(ini.to_string().unwrap(), text);
+
+        ini.set("quoted", "single", "two\nlines");
+        ini.set("quoted", "new", "[not a section");
+        let out = ini.to_string().unwrap();
+        assert!(out.contains("single = \"two\\nlines\"\n"));
+        assert!(out.contains("new = \"[not a section\"\n"));
+    }
+
+    #[test]
+    fn test_quoted_values_survive_save() {
+        let values = ["  spaced  ", "#not a comment", ";nor this", "[nor a section]", "line\nbreak\ttab", "\"quotes\" and \\", "'single'", ""];
+        let mut ini = Ini::default();
+        for (i, v) in values.iter().enumerate() {
+            ini.set("values", &format!("key{}", i), v);
+        }
+        let read = Ini::from_string(ini.to_string().unwrap()).unwrap();
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(read.get("values", &format!("key{}", i)).unwrap(), *v);
+        }
+    }
+
+    #[test]
+    fn test_quotes_read_as_written() {
+        let text = "[a]\nprogram = \"C:\\Program Files\\App\"\nnew = \"C:\\new\\file\"\ntitle = 'Tis the season\nmsg = \"hi\" she said\n";
+        let mut ini = Ini::from_string(text.to_string()).unwrap();
+        assert_eq!(ini.get("a", "program").unwrap(), "\"C:\\Program Files\\App\"");
+        assert_eq!(ini.get("a", "new").unwrap(), "\"C:\\new\\file\"");
+        assert_eq!(ini.get("a", "title").unwrap(), "'Tis the season");
+        assert_eq!(ini.get("a", "msg").unwrap(), "\"hi\" she said");
+        assert_eq!(ini.to_string().unwrap(), text);
+
+        // Written back quoted, so they read back the same
+        ini.set("a", "msg", "\"hi\" she said");
+        ini.set("a", "title", "'Tis the season");
+        let read = Ini::from_string(ini.to_string().unwrap()).unwrap();
+        assert_eq!(read.config_map, ini.config_map);
+    }
+
+    #[test]
+    fn test_inline_comments() {
+        let text = "[db]\nport = 5432 ; default\nname = \"a # b\"  # quoted\nurl = http://a;b\n";
+        let options = Options { inline_comments: Some(InlineComments::default()), ..Default::default() };
+        let mut ini = Ini::from_string_with_options(text.to_string(), options).unwrap();
+        assert_eq!(ini.get("db", "port").unwrap(), "5432");
+        assert_eq!(ini.get("db", "name").unwrap(), "a # b");
+        assert_eq!(ini.get("db", "url").unwrap(), "http://a;b");
+        assert_eq!(ini.to_string().unwrap(), text);
+
+        ini.set("db", "port", "6543");
+        ini.set("db", "name", "plain");
+        ini.set("db", "url", "x ; y");
+        assert_eq!(ini.to_string().unwrap(), "[db]\nport = 6543 ; default\nname = plain  # quoted\nurl = \"x ; y\"\n");
+
+        ini.options.inline_comments.as_mut().unwrap().preserve = false;
+        ini.set("db", "port", "1");
+        assert!(ini.to_string().unwrap().starts_with("[db]\nport = 1\nname"));
+
+        let ini = Ini::from_string("[db]\nport = 5432 ; default\n".to_string()).unwrap();
+        assert_eq!(ini.get("db", "port").unwrap(), "5432 ; default");
+    }
+
+    #[test]
+    fn test_multiline_indented() {
+        let text = "[paths]\nsearch =\n    /usr/lib\n    /opt/lib\nname = app\n";
+        let options = Options { multiline: Multiline::Indented, ..Default::default() };
+        let mut ini = Ini::from_string_with_options(text.to_string(), options).unwrap();
+        assert_eq!(ini.get("paths", "search").unwrap(), "/usr/lib\n/opt/lib");
+        assert_eq!(ini.get("paths", "name").unwrap(), "app");
+        assert_eq!(ini.to_string().unwrap(), text);
+
+        ini.set("paths", "search", "/lib");
+        ini.set("paths", "extra", "a\nb");
+        assert_eq!(ini.to_string().unwrap(), "[paths]\nsearch = /lib\nname = app\nextra = a\n    b\n");
+
+        let read = Ini::from_string_with_options(ini.to_string().unwrap(), ini.options.clone()).unwrap();
+        assert_eq!(read.config_map, ini.config_map);
+        assert!(matches!(Ini::from_string(text.to_string()), Err(Error::Syntax(e)) if e.line == 3));
+    }
+
+    #[test]
+    fn test_multiline_backslash() {
+        let text = "[db]\nquery = SELECT * \\\n    FROM users \\\n    WHERE id = 1\nother = x\n";
+        let options = Options { multiline: Multiline::Backslash, ..Default::default() };
+        let mut ini = Ini::from_string_with_options(text.to_string(), options).unwrap();
+        assert_eq!(ini.get("db", "query").unwrap(), "SELECT * FROM users WHERE id = 1");
+        assert_eq!(ini.to_string().unwrap(), text);
+
+        let long = "Server=db.example.com; Database=inventory; User Id=admin; Password=hunter2; Encrypt=true";
+        ini.set("db", "query", long);
+        let out = ini.to_string().unwrap();
+        assert!(out.lines().count() > 3 && out.lines().all(|l| l.len() <= 80), "{}", out);
+        let read = Ini::from_string_with_options(out, ini.options.clone()).unwrap();
+        assert_eq!(read.get("db", "query").unwrap(), long);
+        assert_eq!(read.get("db", "other").unwrap(), "x");
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let text = "[Database]\nHost = localhost\n[database]\nport = 5432\n";
+        let options = Options { case_insensitive: true, ..Default::default() };
+        let mut ini = Ini::from_string_with_options(text.to_string(), options).unwrap();
+        assert_eq!(ini.config_map.keys().collect::<Vec<_>>(), ["Database"]);
+        assert_eq!(ini.get("DATABASE", "host").unwrap(), "localhost");
+        assert_eq!(ini.get("database", "PORT").unwrap(), "5432");
+
+        ini.set("DATABASE", "HOST", "db.internal");
+        ini.set("database", "User", "admin");
+        ini.set("New", "key", "value");
+        ini.set("new", "KEY", "changed");
+        assert_eq!(ini.to_string().unwrap(), "[Database]\nHost = db.internal\n[database]\nport = 5432\nUser = admin\n[New]\nkey = changed\n");
+
+        ini.remove("DataBase", "PORT");
+        ini.remove_section("NEW");
+        assert_eq!(ini.to_string().unwrap(), "[Database]\nHost = db.internal\nUser = admin\n[database]\n");
+
+        let ini = Ini::from_string(text.to_string()).unwrap();
+        assert_eq!(ini.get("database", "host"), None);
+        assert_eq!(ini.config_map.len(), 2);
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let text = "[production]\nhost = example.com\nurl = https://${host}/\ndebug = false\n[staging : production]\nhost = staging.example.com\n[dev:staging]\ndebug = true\n";
+        let options = Options { inheritance: true, interpolation: Interpolation::Extended, ..Default::default() };
+        let mut ini = Ini::from_string_with_options(text.to_string(), options).unwrap();
+        assert_eq!(ini.parent("dev"), Some("staging"));
+        assert_eq!(ini.parent("production"), None);
+        assert_eq!(ini.get("dev", "debug").unwrap(), "true");
+        assert_eq!(ini.get("dev", "host").unwrap(), "staging.example.com");
+        assert_eq!(ini.get("staging", "debug").unwrap(), "false");
+        assert_eq!(ini.get("staging", "url").unwrap(), "https://staging.example.com/");
+        assert_eq!(ini.get("dev", "missing"), None);
+        assert!(!ini.config_map["dev"].contains_key("host"));
+
+        ini.set("staging", "debug", "true");
+        assert_eq!(ini.to_string().unwrap(), text.replace("[dev", "debug = true\n[dev"));
+
+        let ini = Ini::from_string("[staging : production]\nkey = 1\n".to_string()).unwrap();
+        assert_eq!(ini.get("staging : production", "key").unwrap(), "1");
+    }
+
+    #[test]
+    fn test_inheritance_errors() {
+        let options = Options { inheritance: true, ..Default::default() };
+        let cases = [
+            ("[staging : production]\n[production]\n", SyntaxErrorKind::MissingParent, 1),
+            ("[a]\n[b : a]\n[a : b]\n", SyntaxErrorKind::InheritanceCycle, 3),
+            ("[a]\n[a : a]\n", SyntaxErrorKind::InheritanceCycle, 2),
+        ];
+        for (text, kind, line) in cases {
+            match Ini::from_string_with_options(text.to_string(), options.clone()) {
+                Err(Error::Syntax(e)) => assert_eq!((e.kind, e.line), (kind, line), "{}", text),
+                _ => panic!("expected a syntax error for {:?}", text),
+            }
+        }
+    }
+
+    #[test]
+    fn test_inheritance_edits() {
+        let options = Options { inheritance: true, ..Default::default() };
+        let read = |text: String| Ini::from_string_with_options(text, options.clone()).unwrap();
+
+        // Children of a removed section inherit from its parent
+        let mut ini = read("[g]\nkey = 1\n[p : g]\n[c : p]\n[d : p]\n".to_string());
+        ini.remove_section("p");
+        assert_eq!(ini.parent("p"), None);
+        assert_eq!(ini.parent("c"), Some("g"));
+        assert_eq!(ini.to_string().unwrap(), "[g]\nkey = 1\n[c : g]\n[d : g]\n");
+        assert_eq!(read(ini.to_string().unwrap()).get("d", "key").unwrap(), "1");
+        ini.remove_section("g");
+        assert_eq!(ini.to_string().unwrap(), "[c]\n[d]\n");
+
+        // Sorting keeps parents before their children
+        let mut ini = read("[b]\nkey = 1\n[a : b]\n[c]\n[0 : c]\n".to_string());
+        ini.sort();
+        assert_eq!(ini.config_map.keys().collect::<Vec<_>>(), ["c", "0", "b", "a"]);
+        assert_eq!(ini.to_string().unwrap(), "[c]\n[0 : c]\n[b]\nkey = 1\n[a : b]\n");
+
+        // Parents can be set on new sections and changed on existing ones
+        let mut ini = read("[base]\nkey = 1\n[other]\n[child]\n[child]\n".to_string());
+        ini.set_parent("new", Some("base")).unwrap();
+        ini.set_parent("child", Some("other")).unwrap();
+        let kind = |x: Result<(), Error>| match x {
+            Err(Error::Inheritance(e)) => e.kind,
+            _ => panic!("expected an inheritance error"),
+        };
+        assert_eq!(kind(ini.set_parent("base", Some("child"))), InheritanceErrorKind::MissingParent);
+        assert_eq!(kind(ini.set_parent("other", Some("missing"))), InheritanceErrorKind::MissingParent);
+        assert_eq!(kind(ini.set_parent("base", Some("base"))), InheritanceErrorKind::Cycle);
+        assert_eq!(kind(ini.set_parent(GLOBAL_SECTION, Some("base"))), InheritanceErrorKind::GlobalSection);
+        assert_eq!(ini.get("new", "key").unwrap(), "1");
+        assert_eq!(ini.to_string().unwrap(), "[base]\nkey = 1\n[other]\n[child : other]\n[child]\n[new : base]\n");
+        ini.set_parent("child", None).unwrap();
+        assert_eq!(ini.to_string().unwrap(), "[base]\nkey = 1\n[other]\n[child]\n[child]\n[new : base]\n");
+        assert_eq!(read(ini.to_string().unwrap()).parent("new"), Some("base"));
+
+        let mut ini = Ini::from_string("[base]\n[child]\n".to_string()).unwrap();
+        assert_eq!(kind(ini.set_parent("child", Some("base"))), InheritanceErrorKind::Disabled);
+        assert_eq!(ini.to_string().unwrap(), "[base]\n[child]\n");
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let text = "[a]\r\nkey = 1\r\n";
+        let mut ini = Ini::from_string(text.to_string()).unwrap();
+        assert_eq!(ini.get("a", "key").unwrap(), "1");
+        ini.set("a", "other", "2");
+        assert_eq!(ini.to_string().unwrap(), "[a]\r\nkey = 1\r\nother = 2\r\n");
+
+        ini.options.line_ending = LineEnding::Lf;
+        assert_eq!(ini.to_string().unwrap(), "[a]\nkey = 1\nother = 2\n");
+        assert_eq!(format!("{}", ini), "[a]\nkey = 1\nother = 2\n");
+
+        // A file mixing new lines is written with the first it has
+        let ini = Ini::from_string("[a]\r\nkey = 1\nother = 2\r\n".to_string()).unwrap();
+        assert_eq!(ini.to_string().unwrap(), "[a]\r\nkey = 1\r\nother = 2\r\n");
+
+        let options = Options { line_ending: LineEnding::CrLf, ..Default::default() };
+        let ini = Ini::from_string_with_options("[a]\nkey = 1\n".to_string(), options).unwrap();
+        assert_eq!(ini.to_string().unwrap(), text);
+
+        let mut ini = Ini::default();
+        ini.set("a", "key", "1");
+        let native = if cfg!(windows) { "\r\n" } else { "\n" };
+        assert_eq!(ini.to_string().unwrap(), format!("[a]{}key=1{}", native, native));
+    }
+
+    #[test]
+    fn test_atomic_save() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("save-tests");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.ini");
+        fs::write(&path, "[a]\nkey = 1\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{symlink, PermissionsExt};
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+            symlink(&path, dir.join("link.ini")).unwrap();
+        }
+
+        let mut ini = Ini::new(path.to_string_lossy().to_string()).unwrap();
+        ini.set("a", "key", "2");
+        assert_eq!(ini.save().unwrap(), 12);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[a]\nkey = 2\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), if cfg!(unix) { 2 } else { 1 });
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+            let mut ini = Ini::new(dir.join("link.ini").to_string_lossy().to_string()).unwrap();
+            ini.set("a", "key", "3");
+            ini.save().unwrap();
+            assert!(fs::symlink_metadata(dir.join("link.ini")).unwrap().file_type().is_symlink());
+            assert_eq!(fs::read_to_string(&path).unwrap(), "[a]\nkey = 3\n");
+        }
+
+        let options = Options { save_mode: SaveMode::InPlace, ..Default::default() };
+        let mut ini = Ini::new_with_options(path.to_string_lossy().to_string(), options).unwrap();
+        ini.set("a", "key", "4");
+        ini.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[a]\nkey = 4\n");
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{Error, Ini, Locking, Options};
+
+/// Added to the name of a file to get the file its lock is held on
+const LOCK_SUFFIX: &str = ".lock";
+/// How long to wait between attempts to take a lock when there is a timeout
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Lock a file, shared for reading or exclusive for writing. The lock is released when the returned file is dropped.
+/// Returns None if there is nothing to lock, see open
+pub(crate) fn lock(location: &str, exclusive: bool, locking: &Locking) -> Result<Option<File>, Error> {
+    let Some(file) = open(location, exclusive)? else {
+        return Ok(None);
+    };
+    let Some(timeout) = locking.timeout else {
+        if exclusive { file.lock()? } else { file.lock_shared()? }
+        return Ok(Some(file));
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let ret = if exclusive { file.try_lock() } else { file.try_lock_shared() };
+        match ret {
+            Ok(()) => return Ok(Some(file)),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+            Err(TryLockError::WouldBlock) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::LockTimeout);
+                }
+                thread::sleep(RETRY_INTERVAL.min(deadline - now));
+            },
+        }
+    }
+}
+
+/// Open the file a lock is held on, `<file>.lock` next to the file. A shared lock only reads it, so an existing one is opened read-only.
+/// If it can't be created, such as in a directory that can't be written to, the file itself is locked instead.
+/// Returns None if neither exists, as a file that can't be created can't be written by anyone else either
+fn open(location: &str, exclusive: bool) -> io::Result<Option<File>> {
+    let path = format!("{}{}", location, LOCK_SUFFIX);
+    if !exclusive && let Ok(x) = File::open(&path) && x.metadata().is_ok_and(|m| m.is_file()) {
+        return Ok(Some(x));
+    }
+    if let Ok(x) = OpenOptions::new().create(true).truncate(false).write(true).open(&path) {
+        return Ok(Some(x));
+    }
+    match File::open(location) {
+        Ok(x) => Ok(Some(x)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl Ini {
+    /// Load an INI file, change it and save it while holding an exclusive lock, so processes doing the same take turns.
+    /// Returns whatever the closure returns. The file is created if it doesn't exist.
+    pub fn modify_locked<R>(location: String, f: impl FnOnce(&mut Ini) -> R) -> Result<R, Error> {
+        Self::modify_locked_with_options(location, Options::default(), f)
+    }
+
+    /// Load an INI file using the provided options, change it and save it while holding an exclusive lock.
+    /// Waits for the lock as set by Options::locking, or as long as it takes if that isn't set.
+    pub fn modify_locked_with_options<R>(location: String, options: Options, f: impl FnOnce(&mut Ini) -> R) -> Result<R, Error> {
+        let _lock = lock(&location, true, &options.locking.clone().unwrap_or_default())?;
+        let mut ini = Self::load(location, options)?;
+        let ret = f(&mut ini);
+        ini.write()?;
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+    use crate::{Error, Ini, Locking, Options};
+    use super::lock;
+
+    fn test_file(name: &str) -> String {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("lock-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_modify_locked() {
+        let path = test_file("counter.ini");
+        let threads: Vec<_> = (0..4).map(|_| {
+            let path = path.clone();
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    Ini::modify_locked(path.clone(), |ini| {
+                        let n = ini.get_as::<u32>("counter", "n").unwrap_or(0);
+                        ini.set("counter", "n", &(n + 1).to_string());
+                    }).unwrap();
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(Ini::new(path).unwrap().get("counter", "n").unwrap(), "40");
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let path = test_file("timeout.ini");
+        fs::write(&path, "[a]\nkey = 1\n").unwrap();
+        let locking = Locking { timeout: Some(Duration::from_millis(50)) };
+        let options = Options { locking: Some(locking.clone()), ..Default::default() };
+
+        let held = lock(&path, true, &Locking::default()).unwrap().unwrap();
+        assert!(matches!(Ini::new_with_options(path.clone(), options.clone()), Err(Error::LockTimeout)));
+        assert!(matches!(Ini::modify_locked_with_options(path.clone(), options.clone(), |_| ()), Err(Error::LockTimeout)));
+        drop(held);
+
+        // Readers share the lock, but keep writers out
+        let shared = lock(&path, false, &locking).unwrap().unwrap();
+        let mut ini = Ini::new_with_options(path.clone(), options).unwrap();
+        ini.set("a", "key", "2");
+        assert!(matches!(ini.save(), Err(Error::LockTimeout)));
+        drop(shared);
+        ini.save().unwrap();
+        assert_eq!(Ini::new(path).unwrap().get("a", "key").unwrap(), "2");
+    }
+
+    #[test]
+    fn test_lock_fallback() {
+        let locking = Locking { timeout: Some(Duration::from_millis(50)) };
+        let options = Options { locking: Some(locking.clone()), ..Default::default() };
+
+        // Where the lock file can't be created, the file itself is locked
+        let path = test_file("fallback.ini");
+        fs::write(&path, "[a]\nkey = 1\n").unwrap();
+        let _ = fs::create_dir(format!("{}.lock", path));
+        let held = lock(&path, true, &locking).unwrap().unwrap();
+        assert!(matches!(lock(&path, true, &locking), Err(Error::LockTimeout)));
+        assert!(matches!(Ini::new_with_options(path.clone(), options.clone()), Err(Error::LockTimeout)));
+        drop(held);
+        assert_eq!(Ini::new_with_options(path, options.clone()).unwrap().get("a", "key").unwrap(), "1");
+
+        // A file that can't be created has nothing to lock
+        let path = format!("{}/missing/missing.ini", Path::new(&test_file("missing")).parent().unwrap().display());
+        assert!(lock(&path, false, &locking).unwrap().is_none());
+        assert!(Ini::new_with_options(path, options).unwrap().config_map.is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+use crate::{NEW_LINE_CRLF, NEW_LINE_LF};
+
+/// Options controlling how INI data is read and written.
+/// Pass to Ini::new_with_options or Ini::from_string_with_options, the defaults match Ini::new.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// What to do when a key appears more than once in a section
+    pub duplicate_keys: DuplicateKeys,
+    /// What to do when a section header appears more than once
+    pub duplicate_sections: DuplicateSections,
+    /// How keys with more than one value are written when they aren't in the file yet
+    pub list_style: ListStyle,
+    /// Strip comments written after values. None (default) reads everything after `=` as the value
+    pub inline_comments: Option<InlineComments>,
+    /// How values can be written across more than one line
+    pub multiline: Multiline,
+    /// How references to other values are expanded when reading them with get
+    pub interpolation: Interpolation,
+    /// Expand environment variables when reading values with get. None (default) leaves them as written
+    pub environment: Option<Environment>,
+    /// Read files named by `!include` and `!includedir` directives. None (default) treats them as invalid lines
+    pub includes: Option<Includes>,
+    /// Match section and key names ignoring case, as Windows does, so `[Database]` and `[database]` are the same section.
+    /// Names keep the spelling they were first read or set with. Defaults to false
+    pub case_insensitive: bool,
+    /// Read `[child : parent]` headers as sections inheriting the keys of a section declared before them, as in Zend's INI config.
+    /// Keys missing from a section are looked up through its parents. When false (default) the whole header is the section name
+    pub inheritance: bool,
+    /// The new line written between lines when saving
+    pub line_ending: LineEnding,
+    /// How files are written when saving
+    pub save_mode: SaveMode,
+    /// Lock the file while reading and saving, so processes sharing it don't read it half written. None (default) doesn't lock.
+    /// Use Ini::modify_locked to keep the file locked from reading through to saving
+    pub locking: Option<Locking>,
+    /// What save does if the file was changed by something else since it was read or saved
+    pub conflicts: Conflicts,
+}
+
+/// What to do when a key appears more than once in a section
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Return a SyntaxErrorKind::DuplicateKey error
+    Error,
+    /// Keep the first value, later ones are ignored
+    FirstWins,
+    /// Keep the last value, earlier ones are ignored
+    #[default]
+    LastWins,
+    /// Keep every value, in the order they were read. Keys written as `key[]` are always kept this way
+    KeepAll,
+}
+
+/// What to do when a section header appears more than once
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateSections {
+    /// Return a SyntaxErrorKind::DuplicateSection error
+    Error,
+    /// Keep the first section, later ones are ignored
+    FirstWins,
+    /// Keep the last section, earlier ones are ignored
+    LastWins,
+    /// Add the keys from each to the same section, repeated keys are handled by DuplicateKeys
+    #[default]
+    Merge,
+}
+
+/// How a key with more than one value is written.
+/// Unless DuplicateKeys::KeepAll is used, repeated keys would be read back as a single value, so they are always written as `key[]`.
+/// Keys already in the file keep the form they were read in where they can.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListStyle {
+    /// Repeat the key for each value, `plugin=a` then `plugin=b`. Only used with DuplicateKeys::KeepAll
+    #[default]
+    Repeat,
+    /// Repeat the key with `[]` after it for each value, `plugin[]=a` then `plugin[]=b`. These are always read as a list
+    Brackets,
+}
+
+/// How values can be written across more than one line.
+/// Values that are changed are written back in the same style, falling back to quotes when they can't be.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Multiline {
+    /// Every value is on the same line as its key
+    #[default]
+    Off,
+    /// A line ending with `\` continues on the next, which has its leading whitespace removed. The lines are joined without a new line.
+    /// Long values are wrapped at spaces when written
+    Backslash,
+    /// Indented lines after a key continue its value, as in Python's configparser. The lines are joined with new lines.
+    /// Values containing new lines are written this way
+    Indented,
+}
+
+/// The new line written between lines when saving.
+/// Files are read the same way whichever is used, lines can end with `\n` or `\r\n`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// Use the new line the file was read with, or Native if it had none.
+    /// A file mixing `\n` and `\r\n` is written with whichever comes first, so it changes when saved even if nothing else did
+    #[default]
+    Preserve,
+    /// `\r\n` on Windows and `\n` everywhere else
+    Native,
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+impl LineEnding {
+    /// The new line to write, given the one the file was read with if any
+    pub(crate) fn resolve(self, read: Option<&'static str>) -> &'static str {
+        match self {
+            LineEnding::Preserve => read.unwrap_or_else(|| LineEnding::Native.resolve(None)),
+            LineEnding::Native if cfg!(windows) => NEW_LINE_CRLF,
+            LineEnding::Native | LineEnding::Lf => NEW_LINE_LF,
+            LineEnding::CrLf => NEW_LINE_CRLF,
+        }
+    }
+}
+
+/// How files are written when saving
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SaveMode {
+    /// Write a temporary file in the same directory then rename it over the file, so a crash or full disk never leaves it half written.
+    /// The file keeps its permissions, and saving through a symlink writes to the file it points to
+    #[default]
+    Atomic,
+    /// Truncate the file and write over it. Faster, but the file can be left empty or half written if saving is interrupted
+    InPlace,
+}
+
+/// What save does if the file was changed by something else since it was read or saved.
+/// A file is taken as unchanged if its size and a hash of its contents are the same. The modified time isn't used, as it can be set back.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Conflicts {
+    /// Return Error::Conflict without writing anything. Ini::reconcile reads the file again keeping the changes made since
+    #[default]
+    Error,
+    /// Write over the file, losing the other changes
+    Overwrite,
+}
+
+/// Advisory locking of a file shared with other processes. Reading takes a shared lock, saving an exclusive one.
+/// The lock is held on a `.lock` file next to the file, as saving atomically replaces the file itself.
+/// Only processes that lock the same way wait for each other, the file can still be written by anything else.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Locking {
+    /// How long to wait for the lock before returning Error::LockTimeout. None (default) waits as long as it takes
+    pub timeout: Option<Duration>,
+}
+
+/// How references to other values are expanded when reading them with get.
+/// Values are always saved as they were written, get_raw reads them without expanding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Values are read as they are written
+    #[default]
+    Off,
+    /// `${key}` is replaced with a key from the same section, and `${section:key}` with one from another section, as in Python's ExtendedInterpolation.
+    /// `${:key}` refers to GLOBAL_SECTION, and `$$` is read as `$`
+    Extended,
+}
+
+/// Where environment variables are read from when expanding values.
+/// `${NAME}` is replaced with the variable, returning Error::Interpolation if it isn't set.
+/// `${NAME:-default}` uses the default if it isn't set or is empty, and `${NAME:?message}` returns an error including the message.
+/// When Options::interpolation is also set, `${name}` refers to a key if one exists and a variable otherwise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Environment {
+    /// Variables to use instead of the process environment, e.g. for tests. None (default) reads the process environment
+    pub vars: Option<HashMap<String, String>>,
+    /// Also expand Windows style `%NAME%`, which is left as written if the variable isn't set. `%%` is read as `%`. Defaults to true
+    pub windows_style: bool,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment { vars: None, windows_style: true }
+    }
+}
+
+impl Environment {
+    /// Read a variable, from vars if set or the process environment otherwise
+    pub(crate) fn var(&self, name: &str) -> Option<String> {
+        match &self.vars {
+            Some(vars) => vars.get(name).cloned(),
+            None => env::var(name).ok(),
+        }
+    }
+}
+
+/// How include directives are followed.
+/// `!include other.ini` reads another file as if its lines were written in place of the directive, and `!includedir conf.d/` does the same for every `.ini` and `.cnf` file in a directory, in name order.
+/// Relative paths are from the directory of the including file. Changes to keys are saved to the file they were read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Includes {
+    /// How deeply includes can be nested before SyntaxErrorKind::IncludeDepth is returned. Defaults to 10
+    pub max_depth: usize,
+}
+
+impl Default for Includes {
+    fn default() -> Self {
+        Includes { max_depth: 10 }
+    }
+}
+
+/// How comments after a value are recognised, e.g. `port = 5432 ; default`.
+/// Quoted values are read up to their closing quote, so markers inside the quotes are part of the value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InlineComments {
+    /// Text that starts a comment. Defaults to `#` and `;`
+    pub markers: Vec<String>,
+    /// Only treat a marker as a comment when whitespace comes before it, so `a;b` is read as a value. Defaults to true
+    pub whitespace_before: bool,
+    /// Keep the comment on the line when its value is changed. Defaults to true, when false changed lines lose their comment
+    pub preserve: bool,
+}
+
+impl Default for InlineComments {
+    fn default() -> Self {
+        InlineComments { markers: vec!["#".to_string(), ";".to_string()], whitespace_before: true, preserve: true }
+    }
+}
+
+impl InlineComments {
+    /// Where the comment in an unquoted value starts, if it has one
+    pub(crate) fn find(&self, text: &str) -> Option<usize> {
+        text.char_indices().map(|(i, _)| i).find(|i| {
+            self.starts_comment(&text[*i..])
+                && (*i == 0 || !self.whitespace_before || text[..*i].ends_with(char::is_whitespace))
+        })
+    }
+
+    /// If the text starts with one of the markers
+    pub(crate) fn starts_comment(&self, text: &str) -> bool {
+        self.markers.iter().any(|m| !m.is_empty() && text.starts_with(m.as_str()))
+    }
+}
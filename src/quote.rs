@@ -0,0 +1,269 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::ops::Range;
+use crate::{InlineComments, Multiline, Options};
+
+const QUOTE_DOUBLE: char = '"';
+const QUOTE_SINGLE: char = '\'';
+const ESCAPE: char = '\\';
+/// Ends a line that continues on the next when using Multiline::Backslash
+pub(crate) const CONTINUATION: char = '\\';
+/// Written before each continuation line
+const CONTINUATION_INDENT: &str = "    ";
+/// Values that would make a line longer than this are wrapped when using Multiline::Backslash
+const WRAP_WIDTH: usize = 80;
+
+/// Read the value of an entry from its lines, the first holding the key and any others continuing the value.
+/// `value_start` is where the text after `=` starts in the first line.
+/// Returns the value and where it was written within the lines joined with `\n`. A value over more than one line runs to the end.
+pub(crate) fn read_lines(lines: &[String], value_start: usize, options: &Options) -> (String, Range<usize>) {
+    let comments = options.inline_comments.as_ref();
+    let first = &lines[0][value_start..];
+    if lines.len() == 1 {
+        let (value, span) = read_value(first, comments);
+        return (value, value_start + span.start..value_start + span.end);
+    }
+
+    let start = value_start + first.len() - first.trim_start().len();
+    let end = lines.iter().map(|l| l.len() + 1).sum::<usize>() - 1;
+    match options.multiline {
+        Multiline::Indented => {
+            let mut values: Vec<String> = lines.iter().enumerate()
+                .map(|(n, line)| read_value(if n == 0 { first } else { line }, comments).0)
+                .collect();
+            // The value can start on the line after the key
+            if values[0].is_empty() {
+                values.remove(0);
+            }
+            (values.join("\n"), start..end)
+        },
+        _ => {
+            let mut text = String::new();
+            for (n, line) in lines.iter().enumerate() {
+                let part = if n == 0 { first } else { line.trim_start() };
+                match part.trim_end().strip_suffix(CONTINUATION) {
+                    Some(x) if n + 1 < lines.len() => text.push_str(x),
+                    _ => text.push_str(part),
+                }
+            }
+            (read_value(&text, comments).0, start..end)
+        },
+    }
+}
+
+/// Read the value from the text after `=`, removing any quotes, escapes and inline comment.
+/// A value that isn't quoted cleanly, such as `"C:\new"` or `"hi" she said`, is read as it is written, quotes included.
+/// Returns the value and where it was written within the text, not including the comment or surrounding whitespace.
+pub(crate) fn read_value(text: &str, comments: Option<&InlineComments>) -> (String, Range<usize>) {
+    let start = text.len() - text.trim_start().len();
+    let trimmed = &text[start..];
+    if let Some((value, len)) = unquote(trimmed) {
+        // After a closing quote there can only be whitespace or a comment
+        let rest = &trimmed[len..];
+        let extra = rest.len() - rest.trim_start().len();
+        if rest.trim().is_empty() || comments.is_some_and(|c| c.starts_comment(&rest[extra..])) {
+            return (value, start..start + len);
+        }
+    }
+    let end = comments.and_then(|c| c.find(trimmed)).unwrap_or(trimmed.len());
+    let value = trimmed[..end].trim_end();
+    (value.to_string(), start..start + value.len())
+}
+
+/// Read a value wrapped in quotes, returning None if it isn't quoted, has no closing quote or holds an unknown escape.
+/// Both quote styles accept the escapes `\n`, `\t`, `\r`, `\0`, `\\`, `\"`, `\'` and `\uXXXX`.
+/// Returns the value and the length of the text up to and including the closing quote.
+fn unquote(text: &str) -> Option<(String, usize)> {
+    let quote = text.chars().next().filter(|x| *x == QUOTE_DOUBLE || *x == QUOTE_SINGLE)?;
+    let mut ret = String::with_capacity(text.len());
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            return Some((ret, i + c.len_utf8()));
+        }
+        if c != ESCAPE {
+            ret.push(c);
+            continue;
+        }
+        let escaped = match chars.next()?.1 {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            x if x == ESCAPE || x == QUOTE_DOUBLE || x == QUOTE_SINGLE => x,
+            'u' => {
+                let hex = text.get(i + 2..i + 6).filter(|x| x.chars().all(|c| c.is_ascii_hexdigit()))?;
+                chars.nth(3);
+                char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+            },
+            _ => return None,
+        };
+        ret.push(escaped);
+    }
+    None
+}
+
+/// Write a value so it reads back the same, using more than one line if the Multiline option allows it.
+/// `before` is the length of the line before the value, so long values can be wrapped.
+/// Lines are separated by `\n`.
+pub(crate) fn write_value<'a>(value: &'a str, before: usize, options: &Options) -> Cow<'a, str> {
+    let plain = |x: &str| !x.is_empty() && !needs_quotes(x, options);
+    match options.multiline {
+        Multiline::Indented if value.contains('\n') && value.split('\n').all(plain) => {
+            Cow::from(value.replace('\n', &format!("\n{}", CONTINUATION_INDENT)))
+        },
+        Multiline::Backslash if before + value.len() > WRAP_WIDTH && plain(value) => {
+            let mut ret = String::with_capacity(value.len() + 16);
+            let mut width = before;
+            let mut start = 0;
+            // Break after spaces that are followed by something else, as leading whitespace is removed when read back
+            let breaks = value.char_indices().filter(|(i, c)| *i > 0 && *c != ' ' && value[..*i].ends_with(' ')).map(|(i, _)| i);
+            for end in breaks.chain([value.len()]) {
+                let chunk = &value[start..end];
+                if start > 0 && width + chunk.len() + 1 > WRAP_WIDTH {
+                    ret.push(CONTINUATION);
+                    ret.push('\n');
+                    ret.push_str(CONTINUATION_INDENT);
+                    width = CONTINUATION_INDENT.len();
+                }
+                ret.push_str(chunk);
+                width += chunk.len();
+                start = end;
+            }
+            Cow::from(ret)
+        },
+        _ => quote(value, options),
+    }
+}
+
+/// Write a value on a single line so it reads back the same, adding quotes and escapes only when they are needed.
+/// Values with spaces at either end, control characters, that start with a quote, `[`, `#` or `;`, or that would be read as having a comment are quoted.
+fn quote<'a>(value: &'a str, options: &Options) -> Cow<'a, str> {
+    if !needs_quotes(value, options) {
+        return Cow::from(value);
+    }
+
+    let mut ret = String::with_capacity(value.len() + 2);
+    ret.push(QUOTE_DOUBLE);
+    for c in value.chars() {
+        match c {
+            '\n' => ret.push_str("\\n"),
+            '\t' => ret.push_str("\\t"),
+            '\r' => ret.push_str("\\r"),
+            '\0' => ret.push_str("\\0"),
+            x if x == ESCAPE || x == QUOTE_DOUBLE => {
+                ret.push(ESCAPE);
+                ret.push(x);
+            },
+            x if x.is_control() => { let _ = write!(ret, "\\u{:04x}", x as u32); },
+            x => ret.push(x),
+        }
+    }
+    ret.push(QUOTE_DOUBLE);
+    Cow::from(ret)
+}
+
+fn needs_quotes(value: &str, options: &Options) -> bool {
+    value.trim() != value
+        || value.starts_with([QUOTE_DOUBLE, QUOTE_SINGLE, '[', '#', ';'])
+        || value.chars().any(char::is_control)
+        || options.inline_comments.as_ref().is_some_and(|c| c.find(value).is_some())
+        || (options.multiline == Multiline::Backslash && value.ends_with(CONTINUATION))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quote::{quote, read_lines, read_value, write_value};
+    use crate::{InlineComments, Multiline, Options};
+
+    fn value(text: &str) -> String {
+        read_value(text, None).0
+    }
+
+    fn value_with(text: &str, comments: &InlineComments) -> String {
+        read_value(text, Some(comments)).0
+    }
+
+    #[test]
+    fn test_read_value() {
+        assert_eq!(read_value(" plain ", None), ("plain".to_string(), 1..6));
+        assert_eq!(value(r#""a \"b\" c""#), "a \"b\" c");
+        assert_eq!(value(r"'it\'s'"), "it's");
+        assert_eq!(value(r#""one\ntwo\tthree\\ \u00e9""#), "one\ntwo\tthree\\ é");
+        assert_eq!(read_value(r#" "  padded  "  "#, None), ("  padded  ".to_string(), 1..13));
+
+        // Anything that doesn't quote cleanly is read as written
+        assert_eq!(read_value(r#" "open "#, None), (r#""open"#.to_string(), 1..6));
+        assert_eq!(value(r#""C:\Program Files\App""#), r#""C:\Program Files\App""#);
+        assert_eq!(value(r#""bad \u12""#), r#""bad \u12""#);
+        assert_eq!(value(r#""done" extra"#), r#""done" extra"#);
+    }
+
+    #[test]
+    fn test_read_value_comments() {
+        let comments = InlineComments::default();
+        assert_eq!(read_value(" 5432 ; default", Some(&comments)), ("5432".to_string(), 1..5));
+        assert_eq!(read_value(" a;b #c", Some(&comments)), ("a;b".to_string(), 1..4));
+        assert_eq!(read_value(r#" "a ; b" # c"#, Some(&comments)), ("a ; b".to_string(), 1..8));
+        assert_eq!(read_value(" ; only a comment", Some(&comments)), (String::new(), 1..1));
+
+        let anywhere = InlineComments { markers: vec!["//".to_string()], whitespace_before: false, ..Default::default() };
+        assert_eq!(value_with(" a//b ; c", &anywhere), "a");
+        assert_eq!(read_value(" 5432 ; default", None).0, "5432 ; default");
+    }
+
+    #[test]
+    fn test_quote() {
+        let plain = Options::default();
+        assert_eq!(quote("plain value", &plain), "plain value");
+        assert_eq!(quote(r"C:\path", &plain), r"C:\path");
+        assert_eq!(quote(" padded", &plain), r#"" padded""#);
+        assert_eq!(quote("[not a section", &plain), r#""[not a section""#);
+        assert_eq!(quote("two\nlines", &plain), r#""two\nlines""#);
+        assert_eq!(quote("\"quoted\"", &plain), r#""\"quoted\"""#);
+        assert_eq!(quote("bell\u{7}", &plain), r#""bell\u0007""#);
+        assert_eq!(quote("a ; b", &plain), "a ; b");
+
+        let options = Options { inline_comments: Some(InlineComments::default()), ..Default::default() };
+        assert_eq!(quote("a ; b", &options), r#""a ; b""#);
+        assert_eq!(quote("a;b", &options), "a;b");
+        let comments = options.inline_comments.as_ref().unwrap();
+        for v in ["", " ", "#hash", ";semi", "'single'", "tab\tand \\ slash", "\u{1b}[0m", "é \u{2028}", "a # b"] {
+            assert_eq!(value_with(&quote(v, &options), comments), v);
+        }
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split('\n').map(String::from).collect()
+    }
+
+    #[test]
+    fn test_read_lines() {
+        let backslash = Options { multiline: Multiline::Backslash, ..Default::default() };
+        let read = read_lines(&lines("k = one \\\n    two\\\nthree"), 3, &backslash);
+        assert_eq!(read, ("one twothree".to_string(), 4..24));
+        assert_eq!(read_lines(&lines("k = \"a \\\n  b\\q\""), 3, &backslash).0, "\"a b\\q\"");
+
+        let indented = Options { multiline: Multiline::Indented, ..Default::default() };
+        assert_eq!(read_lines(&lines("k = a\n  b\n\tc"), 3, &indented).0, "a\nb\nc");
+        assert_eq!(read_lines(&lines("k =\n  a\n  b"), 3, &indented).0, "a\nb");
+        assert_eq!(read_lines(&lines("k = a"), 3, &indented), ("a".to_string(), 4..5));
+    }
+
+    #[test]
+    fn test_write_value() {
+        let indented = Options { multiline: Multiline::Indented, ..Default::default() };
+        assert_eq!(write_value("a\nb", 4, &indented), "a\n    b");
+        assert_eq!(write_value("a\n b", 4, &indented), r#""a\n b""#);
+        assert_eq!(write_value("a\nb", 4, &Options::default()), r#""a\nb""#);
+
+        let backslash = Options { multiline: Multiline::Backslash, ..Default::default() };
+        let long = "Server=db.example.com;Database=inventory;UserId=admin;Password=hunter2;Encrypt=true;TrustServerCertificate=false";
+        assert_eq!(write_value(long, 4, &backslash), long);
+        let words = long.replace(';', "; ");
+        let written = format!("k = {}", write_value(&words, 4, &backslash));
+        assert!(written.lines().count() > 1 && written.lines().all(|l| l.len() <= 80), "{}", written);
+        assert_eq!(read_lines(&lines(&written), 3, &backslash).0, words);
+        assert_eq!(write_value(r"C:\dir\", 4, &backslash), r#""C:\\dir\\""#);
+    }
+}
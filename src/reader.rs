@@ -0,0 +1,229 @@
+use std::io::{self, BufRead};
+use std::ops::Range;
+use crate::include::{self, Directive};
+use crate::{quote, Error, Multiline, Options, SyntaxError, SyntaxErrorKind};
+use crate::{CONFIG_COMMENT_HASH, CONFIG_COMMENT_SEMI, CONFIG_KVP_SPLIT, CONFIG_PARENT_SPLIT, CONFIG_SECTION_END, CONFIG_SECTION_START, LIST_SUFFIX, NEW_LINE_CRLF, NEW_LINE_LF};
+
+/// Reads INI data one line at a time, as a stream of events.
+/// Only the line being read is held in memory, so files of any size can be read. Ini::new is built on this.
+/// Each line is read as it is written, so duplicate keys and sections are all reported, and includes aren't followed.
+/// A line that can't be read gives an error and reading carries on with the next, an error reading the data itself ends it.
+pub struct Reader<R> {
+    read: R,
+    options: Options,
+    /// How many lines have been read
+    line: usize,
+    /// A line read ahead while looking for the end of an indented value, with its line number
+    peeked: Option<(usize, String)>,
+    /// Set once the end has been reached or reading failed
+    done: bool,
+    /// The first new line found
+    pub(crate) new_line: Option<&'static str>,
+    /// If the last line read was terminated by a new line. True if there were no lines
+    pub(crate) trailing_newline: bool,
+}
+
+/// A line, or lines for a multi-line value, read by a Reader
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// The line number the event starts on, counting from 1
+    pub line: usize,
+    /// What was read
+    pub kind: EventKind,
+    /// The text as it was written, the lines of a multi-line value joined with `\n`
+    pub raw: String,
+    /// Where the key and value are within raw, for entries
+    pub(crate) key_span: Range<usize>,
+    pub(crate) value_span: Range<usize>,
+}
+
+/// What a line holds
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EventKind {
+    /// A blank line
+    Blank,
+    /// A comment on its own line
+    Comment,
+    /// A section header. Parent is set for `[child : parent]` when using Options::inheritance
+    Section { name: String, parent: Option<String> },
+    /// A key and its value, with quotes, escapes and any inline comment removed. List is set for keys written as `key[]`
+    Entry { key: String, value: String, list: bool },
+    /// An include directive, when using Options::includes. Dir is set for `!includedir`
+    Include { path: String, dir: bool },
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Read INI data from anything buffered, such as a BufReader over a file or a byte slice
+    pub fn new(read: R) -> Reader<R> {
+        Self::new_with_options(read, Options::default())
+    }
+
+    /// Read INI data using the provided options
+    pub fn new_with_options(read: R, options: Options) -> Reader<R> {
+        Reader { read, options, line: 0, peeked: None, done: false, new_line: None, trailing_newline: true }
+    }
+
+    /// Read the next line without its new line, with its line number. Returns None at the end
+    fn next_line(&mut self) -> io::Result<Option<(usize, String)>> {
+        if let Some(x) = self.peeked.take() {
+            return Ok(Some(x));
+        }
+        let mut line = String::new();
+        if self.read.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        self.trailing_newline = line.ends_with(NEW_LINE_LF);
+        if self.trailing_newline {
+            line.pop();
+            let crlf = line.ends_with('\r');
+            if crlf {
+                line.pop();
+            }
+            self.new_line.get_or_insert(if crlf { NEW_LINE_CRLF } else { NEW_LINE_LF });
+        }
+        Ok(Some((self.line, line)))
+    }
+
+    /// Work out what a line holds, reading any lines that continue it
+    fn read_event(&mut self, n: usize, raw: String) -> Result<Event, Error> {
+        let event = |kind: EventKind, raw: String| Event { line: n, kind, raw, key_span: 0..0, value_span: 0..0 };
+        let trimmed = raw.trim();
+        if trimmed.is_empty() {
+            return Ok(event(EventKind::Blank, raw));
+        }
+        if trimmed.starts_with(CONFIG_COMMENT_HASH) || trimmed.starts_with(CONFIG_COMMENT_SEMI) {
+            return Ok(event(EventKind::Comment, raw));
+        }
+
+        if self.options.includes.is_some() && let Some(directive) = include::directive(trimmed) {
+            let kind = match directive {
+                Directive::File(x) => EventKind::Include { path: x.to_string(), dir: false },
+                Directive::Dir(x) => EventKind::Include { path: x.to_string(), dir: true },
+            };
+            return Ok(event(kind, raw));
+        }
+
+        if trimmed.starts_with(CONFIG_SECTION_START) {
+            let Some(end) = trimmed.find(CONFIG_SECTION_END) else {
+                return Err(SyntaxError::new(SyntaxErrorKind::UnterminatedSection, n, &raw).into());
+            };
+            let header = trimmed[CONFIG_SECTION_START.len()..end].trim();
+            let (name, parent) = match header.split_once(CONFIG_PARENT_SPLIT) {
+                Some((name, parent)) if self.options.inheritance => (name.trim_end(), Some(parent.trim_start().to_string())),
+                _ => (header, None),
+            };
+            let kind = EventKind::Section { name: name.to_string(), parent };
+            return Ok(event(kind, raw));
+        }
+
+        let Some(split) = raw.find(CONFIG_KVP_SPLIT) else {
+            return Err(SyntaxError::new(SyntaxErrorKind::InvalidLine, n, &raw).into());
+        };
+        let k = &raw[..split];
+        let key_text = k.trim();
+        let key_start = k.len() - k.trim_start().len();
+        let key_span = key_start..key_start + key_text.len();
+        let (key, list) = match key_text.strip_suffix(LIST_SUFFIX) {
+            Some(x) => (x.trim_end().to_string(), true),
+            None => (key_text.to_string(), false),
+        };
+        let value_start = split + CONFIG_KVP_SPLIT.len();
+
+        // Take any lines continuing the value
+        let mut lines = vec![raw];
+        match self.options.multiline {
+            Multiline::Off => {},
+            Multiline::Backslash => {
+                while lines[lines.len() - 1].trim_end().ends_with(quote::CONTINUATION) && let Some((_, next)) = self.next_line()? {
+                    lines.push(next);
+                }
+            },
+            Multiline::Indented => {
+                while let Some((i, next)) = self.next_line()? {
+                    if !next.starts_with([' ', '\t']) || next.trim().is_empty() {
+                        self.peeked = Some((i, next));
+                        break;
+                    }
+                    lines.push(next);
+                }
+            },
+        }
+        let (value, value_span) = quote::read_lines(&lines, value_start, &self.options);
+        // Lines of a value are kept joined with \n, and written with the file's new line
+        Ok(Event { line: n, kind: EventKind::Entry { key, value, list }, raw: lines.join(NEW_LINE_LF), key_span, value_span })
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_line() {
+            Ok(Some((n, raw))) => Some(self.read_event(n, raw)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e.into()))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use crate::{Error, EventKind, Multiline, Options, Reader, SyntaxErrorKind};
+
+    #[test]
+    fn test_reader() {
+        let text = "; comment\r\n\r\n[server]\r\nport = 80 \r\nplugin[] = \"a b\"\r\n[server]\r\nport = 81";
+        let events: Vec<_> = Reader::new(BufReader::new(text.as_bytes())).map(Result::unwrap).collect();
+        let kinds: Vec<_> = events.iter().map(|e| (e.line, e.kind.clone())).collect();
+        let entry = |key: &str, value: &str, list| EventKind::Entry { key: key.to_string(), value: value.to_string(), list };
+        let section = |name: &str| EventKind::Section { name: name.to_string(), parent: None };
+        assert_eq!(kinds, [
+            (1, EventKind::Comment),
+            (2, EventKind::Blank),
+            (3, section("server")),
+            (4, entry("port", "80", false)),
+            (5, entry("plugin", "a b", true)),
+            (6, section("server")),
+            (7, entry("port", "81", false)),
+        ]);
+        assert_eq!(events[3].raw, "port = 80 ");
+    }
+
+    #[test]
+    fn test_reader_multiline() {
+        let options = Options { multiline: Multiline::Indented, ..Default::default() };
+        let mut reader = Reader::new_with_options("[a]\nkey =\n  one\n  two\n\nnext = 1\n".as_bytes(), options);
+        assert!(matches!(reader.nth(1), Some(Ok(e)) if e.line == 2 && e.raw == "key =\n  one\n  two" && e.kind == EventKind::Entry { key: "key".to_string(), value: "one\ntwo".to_string(), list: false }));
+        assert!(matches!(reader.next(), Some(Ok(e)) if e.line == 5 && e.kind == EventKind::Blank));
+        assert!(matches!(reader.next(), Some(Ok(e)) if e.line == 6));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_reader_errors() {
+        let mut reader = Reader::new("[a\nnot a key\nkey = 1\n".as_bytes());
+        let kind = |x: Option<Result<_, Error>>| match x {
+            Some(Err(Error::Syntax(e))) => (e.kind, e.line),
+            _ => panic!("expected a syntax error"),
+        };
+        assert_eq!(kind(reader.next()), (SyntaxErrorKind::UnterminatedSection, 1));
+        assert_eq!(kind(reader.next()), (SyntaxErrorKind::InvalidLine, 2));
+        assert!(matches!(reader.next(), Some(Ok(e)) if e.line == 3));
+
+        let mut reader = Reader::new(&b"[a]\nkey = \xff\n"[..]);
+        assert!(matches!(reader.nth(1), Some(Err(Error::Io(_)))));
+        assert!(reader.next().is_none());
+    }
+}
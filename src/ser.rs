@@ -0,0 +1,635 @@
+use std::fmt;
+use serde::ser::{self, Impossible, Serialize};
+use crate::{Error, Ini, SerdeError, GLOBAL_SECTION};
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Serde(SerdeError { section: None, key: None, message: msg.to_string() })
+    }
+}
+
+/// Serialize a type to an INI string.
+/// Fields of the top level struct become sections, and their fields the keys within.
+/// Top level fields that are single values become global keys.
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    Ini::from_serialize(value)?.to_string()
+}
+
+impl Ini {
+    /// Create an INI structure from a serializable type. Does not set the config_file so save doesn't work unless set manually.
+    /// Fields of the top level struct become sections, and their fields the keys within.
+    /// Top level fields that are single values become global keys.
+    /// Values that are None are left out, and sequences are written as a key with more than one value, or an empty value if they are empty.
+    /// Anything nested deeper returns an error.
+    pub fn from_serialize<T: Serialize + ?Sized>(value: &T) -> Result<Ini, Error> {
+        let mut ret = Ini::default();
+        value.serialize(IniSerializer { ini: &mut ret })?;
+        Ok(ret)
+    }
+}
+
+const TOP_LEVEL: &str = "expected a struct or map of sections";
+const SECTION: &str = "expected a struct or map to write as a section, or a single value to write as a global key";
+const VALUE: &str = "nested structs, maps and sequences can't be written as a value";
+const MAP_KEY: &str = "map keys must be a single value";
+
+/// Implement the scalar serializer methods by returning an error, for serializers that only take structs and maps
+macro_rules! reject_scalars {
+    ($msg:expr) => {
+        fn serialize_bool(self, _: bool) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_i8(self, _: i8) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_i16(self, _: i16) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_i32(self, _: i32) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_i64(self, _: i64) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_u8(self, _: u8) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_u16(self, _: u16) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_u32(self, _: u32) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_u64(self, _: u64) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_f32(self, _: f32) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_f64(self, _: f64) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_char(self, _: char) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_str(self, _: &str) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_bytes(self, _: &[u8]) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_unit(self) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_unit_struct(self, _: &'static str) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_unit_variant(self, _: &'static str, _: u32, _: &'static str) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, _: &'static str, _: &T) -> Result<(), Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeTupleStruct, Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_tuple_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self::SerializeTupleVariant, Error> { Err(ser::Error::custom($msg)) }
+        fn serialize_struct_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self::SerializeStructVariant, Error> { Err(ser::Error::custom($msg)) }
+    };
+}
+
+/// Serializes the top level struct or map, each field becoming a section
+struct IniSerializer<'a> {
+    ini: &'a mut Ini,
+}
+
+impl<'a> ser::Serializer for IniSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Sections<'a>;
+    type SerializeStruct = Sections<'a>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    reject_scalars!(TOP_LEVEL);
+
+    fn serialize_none(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Sections<'a>, Error> {
+        Ok(Sections { ini: self.ini, key: None })
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Sections<'a>, Error> {
+        Ok(Sections { ini: self.ini, key: None })
+    }
+}
+
+struct Sections<'a> {
+    ini: &'a mut Ini,
+    key: Option<String>,
+}
+
+impl Sections<'_> {
+    fn section<T: Serialize + ?Sized>(&mut self, section: &str, value: &T) -> Result<(), Error> {
+        value.serialize(SectionSerializer { ini: self.ini, section }).map_err(|e| e.at(section, None))
+    }
+}
+
+impl ser::SerializeStruct for Sections<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.section(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for Sections<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(map_key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.key.take().unwrap_or_default();
+        self.section(&key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Serializes a struct or map into a section, each field becoming a key
+/// A single value at the top level is instead written as a global key named after the field
+struct SectionSerializer<'a> {
+    ini: &'a mut Ini,
+    section: &'a str,
+}
+
+impl SectionSerializer<'_> {
+    fn global<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        let values = value.serialize(ValueSerializer)?;
+        set_values(self.ini, GLOBAL_SECTION, self.section, values);
+        Ok(())
+    }
+}
+
+/// Implement the scalar serializer methods by writing the value as a global key
+macro_rules! global_scalars {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<(), Error> {
+                self.global(&v)
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for SectionSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = GlobalList<'a>;
+    type SerializeTuple = GlobalList<'a>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Keys<'a>;
+    type SerializeStruct = Keys<'a>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    global_scalars! {
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+        serialize_bytes: &[u8]
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.global(&())
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), Error> {
+        self.global(&())
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<(), Error> {
+        self.global(variant)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, _: &'static str, _: &T) -> Result<(), Error> {
+        Err(ser::Error::custom(SECTION))
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<GlobalList<'a>, Error> {
+        Ok(GlobalList { ini: self.ini, key: self.section, list: ValueList::default() })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<GlobalList<'a>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(ser::Error::custom(SECTION))
+    }
+
+    fn serialize_tuple_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(ser::Error::custom(SECTION))
+    }
+
+    fn serialize_struct_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self::SerializeStructVariant, Error> {
+        Err(ser::Error::custom(SECTION))
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Keys<'a>, Error> {
+        self.ini.config_map.entry(self.section.to_string()).or_default();
+        Ok(Keys { ini: self.ini, section: self.section, key: None })
+    }
+
+    fn serialize_struct(self, _: &'static str, len: usize) -> Result<Keys<'a>, Error> {
+        self.serialize_map(Some(len))
+    }
+}
+
+/// A sequence at the top level, written as a global key with more than one value
+struct GlobalList<'a> {
+    ini: &'a mut Ini,
+    key: &'a str,
+    list: ValueList,
+}
+
+impl ser::SerializeSeq for GlobalList<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(&mut self.list, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        set_values(self.ini, GLOBAL_SECTION, self.key, Some(self.list.values));
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for GlobalList<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct Keys<'a> {
+    ini: &'a mut Ini,
+    section: &'a str,
+    key: Option<String>,
+}
+
+impl Keys<'_> {
+    fn key<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        let values = value.serialize(ValueSerializer).map_err(|e| e.at(self.section, Some(key)))?;
+        set_values(self.ini, self.section, key, values);
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for Keys<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.key(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for Keys<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(map_key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.key.take().unwrap_or_default();
+        self.key(&key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Write the values of a key, None leaves the key out.
+/// An empty sequence is written as an empty value, which reads back as an empty sequence
+fn set_values(ini: &mut Ini, section: &str, key: &str, values: Option<Vec<String>>) {
+    if let Some(values) = values {
+        let mut values: Vec<&str> = values.iter().map(|x| x.as_str()).collect();
+        if values.is_empty() {
+            values.push("");
+        }
+        ini.set_all(section, key, &values);
+    }
+}
+
+/// Turn a map key into a string, keys must be a single value
+fn map_key<T: Serialize + ?Sized>(key: &T) -> Result<String, Error> {
+    match key.serialize(ValueSerializer)? {
+        Some(mut x) if x.len() == 1 => Ok(x.remove(0)),
+        _ => Err(ser::Error::custom(MAP_KEY)),
+    }
+}
+
+/// Serializes a value to its string form, None if the value should be left out.
+/// A sequence gives one string for each element.
+struct ValueSerializer;
+
+/// Implement serializing a scalar by formatting it with Display
+macro_rules! serialize_display {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<Option<Vec<String>>, Error> {
+                Ok(Some(vec![v.to_string()]))
+            }
+        )*
+    };
+}
+
+/// Collects the elements of a sequence, each must be a single value
+#[derive(Default)]
+struct ValueList {
+    values: Vec<String>,
+}
+
+impl ser::SerializeSeq for ValueList {
+    type Ok = Option<Vec<String>>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        match value.serialize(ValueSerializer)? {
+            Some(x) if x.len() == 1 => self.values.extend(x),
+            None => {},
+            _ => return Err(ser::Error::custom(VALUE)),
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<Vec<String>>, Error> {
+        Ok(Some(self.values))
+    }
+}
+
+impl ser::SerializeTuple for ValueList {
+    type Ok = Option<Vec<String>>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Option<Vec<String>>, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Option<Vec<String>>;
+    type Error = Error;
+    type SerializeSeq = ValueList;
+    type SerializeTuple = ValueList;
+    type SerializeTupleStruct = Impossible<Option<Vec<String>>, Error>;
+    type SerializeTupleVariant = Impossible<Option<Vec<String>>, Error>;
+    type SerializeMap = Impossible<Option<Vec<String>>, Error>;
+    type SerializeStruct = Impossible<Option<Vec<String>>, Error>;
+    type SerializeStructVariant = Impossible<Option<Vec<String>>, Error>;
+
+    serialize_display! {
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Option<Vec<String>>, Error> {
+        match std::str::from_utf8(v) {
+            Ok(x) => Ok(Some(vec![x.to_string()])),
+            Err(_) => Err(ser::Error::custom("bytes must be valid UTF-8")),
+        }
+    }
+
+    fn serialize_none(self) -> Result<Option<Vec<String>>, Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Option<Vec<String>>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Option<Vec<String>>, Error> {
+        Ok(Some(vec![String::new()]))
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Option<Vec<String>>, Error> {
+        Ok(Some(vec![String::new()]))
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<Option<Vec<String>>, Error> {
+        Ok(Some(vec![variant.to_string()]))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> Result<Option<Vec<String>>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, _: &'static str, _: &T) -> Result<Option<Vec<String>>, Error> {
+        Err(ser::Error::custom(VALUE))
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<ValueList, Error> {
+        Ok(ValueList::default())
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<ValueList, Error> {
+        Ok(ValueList::default())
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(ser::Error::custom(VALUE))
+    }
+
+    fn serialize_tuple_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(ser::Error::custom(VALUE))
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(ser::Error::custom(VALUE))
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct, Error> {
+        Err(ser::Error::custom(VALUE))
+    }
+
+    fn serialize_struct_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self::SerializeStructVariant, Error> {
+        Err(ser::Error::custom(VALUE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use serde::{Deserialize, Serialize};
+    use crate::Ini;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Config {
+        general: General,
+        database: Database,
+        cache: Option<Cache>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct General {
+        app_name: String,
+        enabled: bool,
+        mode: Mode,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Mode {
+        Fast,
+        Safe,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Database {
+        host: String,
+        port: u16,
+        timeout: Option<f32>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Cache {
+        size: u32,
+    }
+
+    fn config() -> Config {
+        Config {
+            general: General { app_name: "TestApp".to_string(), enabled: true, mode: Mode::Safe },
+            database: Database { host: "localhost".to_string(), port: 5432, timeout: None },
+            cache: None,
+        }
+    }
+
+    #[test]
+    fn test_to_string() {
+        assert_eq!(
+            crate::to_string(&config()).unwrap(),
+            "[general]\napp_name=TestApp\nenabled=true\nmode=Safe\n[database]\nhost=localhost\nport=5432\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let ini = Ini::from_serialize(&config()).unwrap();
+        assert_eq!(ini.get("database", "port").unwrap(), "5432");
+        assert_eq!(ini.deserialize::<Config>().unwrap(), config());
+
+        let mut map: BTreeMap<String, BTreeMap<String, u32>> = BTreeMap::new();
+        map.entry("limits".to_string()).or_default().insert("max".to_string(), 10);
+        assert_eq!(crate::to_string(&map).unwrap(), "[limits]\nmax=10\n");
+    }
+
+    #[test]
+    fn test_global_fields() {
+        #[derive(Serialize)]
+        struct EditorConfig {
+            root: bool,
+            #[serde(rename = "*")]
+            all: Cache,
+        }
+        assert_eq!(crate::to_string(&EditorConfig { root: true, all: Cache { size: 4 } }).unwrap(), "root=true\n[*]\nsize=4\n");
+    }
+
+    #[test]
+    fn test_lists() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Plugins {
+            order: Vec<String>,
+            loader: Loader,
+        }
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Loader {
+            plugin: Vec<String>,
+            ports: (u16, u16),
+        }
+        let plugins = Plugins {
+            order: vec!["first".to_string()],
+            loader: Loader { plugin: vec!["a".to_string(), "b".to_string()], ports: (80, 443) },
+        };
+        let text = crate::to_string(&plugins).unwrap();
+        assert_eq!(text, "order=first\n[loader]\nplugin[]=a\nplugin[]=b\nports[]=80\nports[]=443\n");
+        assert_eq!(crate::from_str::<Plugins>(&text).unwrap(), plugins);
+
+        let empty = Plugins { order: Vec::new(), loader: Loader { plugin: Vec::new(), ports: (80, 443) } };
+        let text = crate::to_string(&empty).unwrap();
+        assert_eq!(text, "order=\n[loader]\nplugin=\nports[]=80\nports[]=443\n");
+        assert_eq!(crate::from_str::<Plugins>(&text).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_unsupported_shapes() {
+        #[derive(Serialize)]
+        struct Deep {
+            outer: Outer,
+        }
+        #[derive(Serialize)]
+        struct Outer {
+            inner: Cache,
+        }
+        let e = crate::to_string(&Deep { outer: Outer { inner: Cache { size: 1 } } }).err().unwrap();
+        assert_eq!(e.to_string(), "[outer] inner: nested structs, maps and sequences can't be written as a value");
+
+        #[derive(Serialize)]
+        struct Nested {
+            lists: Listed,
+        }
+        #[derive(Serialize)]
+        struct Listed {
+            list: Vec<Vec<u32>>,
+        }
+        let e = crate::to_string(&Nested { lists: Listed { list: vec![vec![1, 2]] } }).err().unwrap();
+        assert_eq!(e.to_string(), "[lists] list: nested structs, maps and sequences can't be written as a value");
+
+        let e = crate::to_string(&5).err().unwrap();
+        assert_eq!(e.to_string(), "expected a struct or map of sections");
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{same_name, Ini, GLOBAL_SECTION};
+
+/// Splits a section name into a path, `[server.http]` is the child `http` of `[server]`
+const SECTION_SEPARATOR: char = '.';
+
+impl Ini {
+    /// Names of the sections directly under a section, treating dotted names as a tree.
+    /// A child is listed if it or anything under it exists, so `[server.http.tls]` makes `server.http` a child of `server`.
+    /// Pass GLOBAL_SECTION for the top level.
+    pub fn children(&self, section: &str) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        for name in self.config_map.keys() {
+            let Some(rest) = self.path_below(name, section) else {
+                continue;
+            };
+            let child = &name[..name.len() - rest.len() + rest.find(SECTION_SEPARATOR).unwrap_or(rest.len())];
+            if !ret.iter().any(|x| same_name(x, child, self.options.case_insensitive)) {
+                ret.push(child.to_string());
+            }
+        }
+        ret
+    }
+
+    /// Copy a section and everything under it into a new Ini, with names relative to it.
+    /// Keys in the section itself become GLOBAL_SECTION, and `[server.http]` becomes `[http]` in the subtree of `server`.
+    /// The copy has the same options but no file, and is written out fresh rather than keeping formatting.
+    pub fn subtree(&self, section: &str) -> Ini {
+        let mut ret = Ini { options: self.options.clone(), ..Default::default() };
+        for (name, keys) in &self.config_map {
+            let name = match self.path_below(name, section) {
+                Some(rest) => rest,
+                None if same_name(name, section, self.options.case_insensitive) => GLOBAL_SECTION,
+                None => continue,
+            };
+            ret.config_map.entry(name.to_string()).or_default().extend(keys.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        ret
+    }
+
+    /// Remove a section and everything under it, so removing `server` also removes `[server.http]`.
+    /// This will not save the file.
+    pub fn remove_tree(&mut self, section: &str) {
+        let case_insensitive = self.options.case_insensitive;
+        let names: Vec<String> = self.config_map.keys()
+            .filter(|x| same_name(x, section, case_insensitive) || self.path_below(x, section).is_some())
+            .cloned().collect();
+        for name in names {
+            self.config_map.shift_remove(&name);
+            self.unlink(&name);
+        }
+    }
+
+    /// Get a value from a section, falling back to the sections above it.
+    /// Looking up `port` in `server.http` tries `[server.http]` then `[server]`, but not GLOBAL_SECTION.
+    pub fn get_or_parent(&self, section: &str, key: &str) -> Option<String> {
+        let mut section = section;
+        loop {
+            if self.values(section, key).is_some() {
+                return self.get(section, key);
+            }
+            section = &section[..section.rfind(SECTION_SEPARATOR)?];
+        }
+    }
+
+    /// The part of a section name below another section, `http.tls` for `server.http.tls` below `server`.
+    /// Returns None if it isn't below it. Every section except GLOBAL_SECTION is below GLOBAL_SECTION
+    fn path_below<'a>(&self, name: &'a str, section: &str) -> Option<&'a str> {
+        if name == GLOBAL_SECTION {
+            return None;
+        }
+        if section == GLOBAL_SECTION {
+            return Some(name);
+        }
+        let rest = name.get(section.len()..)?.strip_prefix(SECTION_SEPARATOR)?;
+        Some(rest).filter(|_| same_name(&name[..section.len()], section, self.options.case_insensitive))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Ini, Options, GLOBAL_SECTION};
+
+    const SERVER: &str = "name = app\n[server]\nport = 80\nhost = 0.0.0.0\n[server.http]\nport = 8080\n[server.grpc.tls]\ncert = a.pem\n[serverless]\nkey = 1\n";
+
+    #[test]
+    fn test_children() {
+        let ini = Ini::from_string(SERVER.to_string()).unwrap();
+        assert_eq!(ini.children(GLOBAL_SECTION), ["server", "serverless"]);
+        assert_eq!(ini.children("server"), ["server.http", "server.grpc"]);
+        assert_eq!(ini.children("server.grpc"), ["server.grpc.tls"]);
+        assert!(ini.children("server.http").is_empty());
+        assert!(ini.children("missing").is_empty());
+    }
+
+    #[test]
+    fn test_subtree() {
+        let ini = Ini::from_string(SERVER.to_string()).unwrap();
+        let server = ini.subtree("server");
+        assert_eq!(server.to_string().unwrap(), "port=80\nhost=0.0.0.0\n[http]\nport=8080\n[grpc.tls]\ncert=a.pem\n");
+        assert_eq!(server.children(GLOBAL_SECTION), ["http", "grpc"]);
+        assert_eq!(ini.subtree(GLOBAL_SECTION).config_map, ini.config_map);
+    }
+
+    #[test]
+    fn test_remove_tree() {
+        let mut ini = Ini::from_string(SERVER.to_string()).unwrap();
+        ini.remove_tree("server");
+        assert_eq!(ini.to_string().unwrap(), "name = app\n[serverless]\nkey = 1\n");
+
+        let options = Options { case_insensitive: true, ..Default::default() };
+        let mut ini = Ini::from_string_with_options(SERVER.to_string(), options).unwrap();
+        ini.remove_tree("SERVER.grpc");
+        assert_eq!(ini.children("Server"), ["server.http"]);
+    }
+
+    #[test]
+    fn test_get_or_parent() {
+        let ini = Ini::from_string(SERVER.to_string()).unwrap();
+        assert_eq!(ini.get_or_parent("server.http", "port").unwrap(), "8080");
+        assert_eq!(ini.get_or_parent("server.http", "host").unwrap(), "0.0.0.0");
+        assert_eq!(ini.get_or_parent("server.grpc.tls", "host").unwrap(), "0.0.0.0");
+        assert_eq!(ini.get_or_parent("server.http", "name"), None);
+        assert_eq!(ini.get_or_parent("server.http", "missing"), None);
+    }
+}
@@ -0,0 +1,91 @@
+use std::any::type_name;
+use std::fmt;
+use std::str::FromStr;
+use crate::{Error, Ini, ValueError};
+
+/// Strings accepted as true by get_bool, compared case insensitively
+const BOOL_TRUE: [&str; 4] = ["true", "yes", "on", "1"];
+/// Strings accepted as false by get_bool, compared case insensitively
+const BOOL_FALSE: [&str; 4] = ["false", "no", "off", "0"];
+
+impl Ini {
+    /// Get a value from the INI file and convert it to any type implementing FromStr.
+    /// Returns Error::MissingKey if the key doesn't exist, or Error::InvalidValue if it couldn't be converted.
+    /// References to other values are expanded first if Options::interpolation is set.
+    pub fn get_as<T>(&self, section: &str, key: &str) -> Result<T, Error>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.try_get(section, key)?;
+        value.parse::<T>().map_err(|e| invalid_value(section, key, &value, type_name::<T>(), e.to_string()))
+    }
+
+    /// Get a value from the INI file as a bool.
+    /// Accepts true/false, yes/no, on/off and 1/0, ignoring case.
+    /// Returns Error::MissingKey if the key doesn't exist, or Error::InvalidValue if it isn't one of these.
+    pub fn get_bool(&self, section: &str, key: &str) -> Result<bool, Error> {
+        let value = self.try_get(section, key)?;
+        parse_bool(&value).ok_or_else(|| invalid_value(section, key, &value, "bool", "expected true/false, yes/no, on/off or 1/0".to_string()))
+    }
+}
+
+/// Parse the bool spellings accepted by get_bool
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    if BOOL_TRUE.iter().any(|x| x.eq_ignore_ascii_case(value)) {
+        Some(true)
+    } else if BOOL_FALSE.iter().any(|x| x.eq_ignore_ascii_case(value)) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn invalid_value(section: &str, key: &str, value: &str, expected: &'static str, reason: String) -> Error {
+    Error::InvalidValue(ValueError {
+        section: section.to_string(),
+        key: key.to_string(),
+        value: value.to_string(),
+        expected,
+        reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Ini};
+
+    const TYPED: &str = "[Server]\nport = 8080\nratio = 0.75\ndebug = Yes\nverbose = off\nname = web\n";
+
+    #[test]
+    fn test_get_as() {
+        let ini = Ini::from_string(TYPED.to_string()).unwrap();
+        assert_eq!(ini.get_as::<u16>("Server", "port").unwrap(), 8080);
+        assert_eq!(ini.get_as::<f64>("Server", "ratio").unwrap(), 0.75);
+        assert_eq!(ini.get_as::<String>("Server", "name").unwrap(), "web");
+    }
+
+    #[test]
+    fn test_get_as_errors() {
+        let ini = Ini::from_string(TYPED.to_string()).unwrap();
+        assert!(matches!(ini.get_as::<u16>("Server", "missing"), Err(Error::MissingKey { .. })));
+
+        let e = ini.get_as::<u8>("Server", "port").err().unwrap();
+        match &e {
+            Error::InvalidValue(v) => {
+                assert_eq!((v.section.as_str(), v.key.as_str(), v.value.as_str()), ("Server", "port", "8080"));
+                assert_eq!(v.expected, "u8");
+            },
+            _ => panic!("expected invalid value"),
+        }
+        assert_eq!(e.to_string(), "invalid value \"8080\" for [Server] port, expected u8: number too large to fit in target type");
+    }
+
+    #[test]
+    fn test_get_bool() {
+        let ini = Ini::from_string(TYPED.to_string()).unwrap();
+        assert!(ini.get_bool("Server", "debug").unwrap());
+        assert!(!ini.get_bool("Server", "verbose").unwrap());
+        assert!(matches!(ini.get_bool("Server", "name"), Err(Error::InvalidValue(_))));
+    }
+}
@@ -0,0 +1,287 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use indexmap::IndexMap;
+#[cfg(target_os = "linux")]
+use inotify::{Inotify, WatchMask};
+use crate::{Error, Ini};
+
+/// How often the watching thread wakes to check for changes and whether it should stop
+const WAKE_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How a watched file is checked for changes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// How long the file must be left alone before it is reloaded, so a burst of writes causes one reload. Defaults to 100ms
+    pub debounce: Duration,
+    /// How often the file is checked when polling. Defaults to 1 second
+    pub poll_interval: Duration,
+    /// Poll the file even where inotify is available, e.g. on network filesystems that don't report changes. Defaults to false
+    pub force_polling: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions { debounce: Duration::from_millis(100), poll_interval: Duration::from_secs(1), force_polling: false }
+    }
+}
+
+/// Something that changed when a watched file was reloaded
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Change {
+    SectionAdded(String),
+    SectionRemoved(String),
+    KeyAdded { section: String, key: String },
+    KeyRemoved { section: String, key: String },
+    /// The key has different values, compared without expanding them
+    KeyChanged { section: String, key: String },
+}
+
+/// Passed to the callback of a Watcher when the file changes
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WatchEvent {
+    /// The file was reloaded, listing what changed. Changes that only touch comments or formatting aren't reported
+    Reloaded(Vec<Change>),
+    /// The file changed but couldn't be read, so the last good config is kept
+    Failed(Error),
+}
+
+/// Reloads an INI file in the background whenever it changes.
+/// Uses inotify on Linux and polls the file elsewhere. Dropping the watcher stops it.
+pub struct Watcher {
+    ini: Arc<RwLock<Ini>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watcher {
+    /// Watch the config_file of an INI file, reloading it with the same options when it changes.
+    /// The callback is run on the watching thread after each reload. Changes made to the INI data that haven't been saved are lost when it reloads.
+    pub fn new(ini: Ini, options: WatchOptions, callback: impl FnMut(WatchEvent) + Send + 'static) -> Result<Watcher, Error> {
+        if ini.config_file.is_empty() {
+            return Err(Error::MissingPath);
+        }
+        let source = Source::new(Path::new(&ini.config_file), &options);
+        let ini = Arc::new(RwLock::new(ini));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (ini, stop) = (ini.clone(), stop.clone());
+            thread::spawn(move || run(source, &ini, &stop, options.debounce, callback))
+        };
+        Ok(Watcher { ini, stop, thread: Some(thread) })
+    }
+
+    /// The INI data as last loaded
+    pub fn ini(&self) -> RwLockReadGuard<'_, Ini> {
+        self.ini.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The INI data shared with the watching thread, for holding on to or editing
+    pub fn shared(&self) -> Arc<RwLock<Ini>> {
+        self.ini.clone()
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(x) = self.thread.take() {
+            let _ = x.join();
+        }
+    }
+}
+
+/// Where changes to the file are found
+enum Source {
+    #[cfg(target_os = "linux")]
+    Inotify { inotify: Inotify, name: std::ffi::OsString, buffer: Vec<u8> },
+    Poll { path: PathBuf, interval: Duration, next: Instant, last: Option<(u64, Option<SystemTime>)> },
+}
+
+impl Source {
+    fn new(path: &Path, options: &WatchOptions) -> Source {
+        #[cfg(target_os = "linux")]
+        if !options.force_polling && let Ok(x) = Self::inotify(path) {
+            return x;
+        }
+        Source::Poll { path: path.to_path_buf(), interval: options.poll_interval, next: Instant::now(), last: file_state(path) }
+    }
+
+    /// Watch the directory holding the file, as saving atomically replaces the file rather than writing to it
+    #[cfg(target_os = "linux")]
+    fn inotify(path: &Path) -> io::Result<Source> {
+        let name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?.to_os_string();
+        let dir = match path.parent() {
+            Some(x) if !x.as_os_str().is_empty() => x,
+            _ => Path::new("."),
+        };
+        let inotify = Inotify::init()?;
+        let mask = WatchMask::CLOSE_WRITE | WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_TO | WatchMask::MOVED_FROM;
+        inotify.watches().add(dir, mask)?;
+        Ok(Source::Inotify { inotify, name, buffer: vec![0; 4096] })
+    }
+
+    /// If the file has changed since this was last called, without waiting
+    fn changed(&mut self) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            Source::Inotify { inotify, name, buffer } => {
+                let mut ret = false;
+                while let Ok(events) = inotify.read_events(buffer) {
+                    ret |= events.into_iter().any(|e| e.name == Some(name.as_os_str()));
+                }
+                ret
+            },
+            Source::Poll { path, interval, next, last } => {
+                if Instant::now() < *next {
+                    return false;
+                }
+                *next = Instant::now() + *interval;
+                let state = file_state(path);
+                let ret = state != *last;
+                *last = state;
+                ret
+            },
+        }
+    }
+}
+
+/// The size and modified time of a file, None if it can't be read
+fn file_state(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    fs::metadata(path).ok().map(|x| (x.len(), x.modified().ok()))
+}
+
+/// Watch for changes until told to stop, reloading once the file has been left alone for the debounce time
+fn run(mut source: Source, ini: &RwLock<Ini>, stop: &AtomicBool, debounce: Duration, mut callback: impl FnMut(WatchEvent)) {
+    let mut pending: Option<Instant> = None;
+    while !stop.load(Ordering::Relaxed) {
+        if source.changed() {
+            pending = Some(Instant::now());
+        }
+        match pending {
+            Some(x) if x.elapsed() >= debounce => {
+                pending = None;
+                if let Some(event) = reload(ini) {
+                    callback(event);
+                }
+            },
+            _ => thread::sleep(WAKE_INTERVAL),
+        }
+    }
+}
+
+/// Load the file again, replacing the INI data if it could be read. Returns None if nothing changed
+fn reload(ini: &RwLock<Ini>) -> Option<WatchEvent> {
+    let (location, options) = {
+        let ini = ini.read().unwrap_or_else(|e| e.into_inner());
+        (ini.config_file.clone(), ini.options.clone())
+    };
+    // A missing file would load as empty, it is more likely part way through being replaced
+    if !Path::new(&location).exists() {
+        return Some(WatchEvent::Failed(io::Error::from(io::ErrorKind::NotFound).into()));
+    }
+    let new = match Ini::new_with_options(location, options) {
+        Ok(x) => x,
+        Err(e) => return Some(WatchEvent::Failed(e)),
+    };
+    let mut ini = ini.write().unwrap_or_else(|e| e.into_inner());
+    let changes = changes(&ini.config_map, &new.config_map);
+    *ini = new;
+    if changes.is_empty() { None } else { Some(WatchEvent::Reloaded(changes)) }
+}
+
+/// What changed between two sets of sections
+fn changes(old: &IndexMap<String, IndexMap<String, Vec<String>>>, new: &IndexMap<String, IndexMap<String, Vec<String>>>) -> Vec<Change> {
+    let mut ret: Vec<Change> = Vec::new();
+    for (section, keys) in old {
+        let now = new.get(section);
+        if now.is_none() {
+            ret.push(Change::SectionRemoved(section.clone()));
+        }
+        for key in keys.keys().filter(|k| now.is_none_or(|s| !s.contains_key(*k))) {
+            ret.push(Change::KeyRemoved { section: section.clone(), key: key.clone() });
+        }
+    }
+    for (section, keys) in new {
+        let before = old.get(section);
+        if before.is_none() {
+            ret.push(Change::SectionAdded(section.clone()));
+        }
+        for (key, values) in keys {
+            match before.and_then(|s| s.get(key)) {
+                None => ret.push(Change::KeyAdded { section: section.clone(), key: key.clone() }),
+                Some(x) if x != values => ret.push(Change::KeyChanged { section: section.clone(), key: key.clone() }),
+                Some(_) => {},
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use crate::{Ini, Change, WatchEvent, WatchOptions, Watcher};
+
+    fn watch(name: &str, force_polling: bool) {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("watch-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name).to_string_lossy().to_string();
+        fs::write(&path, "[a]\nkey = 1\nold = 1\n").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let options = WatchOptions { debounce: Duration::from_millis(50), poll_interval: Duration::from_millis(20), force_polling };
+        let watcher = Watcher::new(Ini::new(path.clone()).unwrap(), options, move |e| tx.send(e).unwrap()).unwrap();
+        let wait = || rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // A burst of writes is one reload
+        fs::write(&path, "[a]\nkey = 2\nold = 1\n").unwrap();
+        fs::write(&path, "[a]\nkey = 3\n[b]\nnew = 1\n").unwrap();
+        match wait() {
+            WatchEvent::Reloaded(x) => assert_eq!(x, [
+                Change::KeyRemoved { section: "a".to_string(), key: "old".to_string() },
+                Change::KeyChanged { section: "a".to_string(), key: "key".to_string() },
+                Change::SectionAdded("b".to_string()),
+                Change::KeyAdded { section: "b".to_string(), key: "new".to_string() },
+            ]),
+            x => panic!("expected a reload, got {:?}", x),
+        }
+        assert_eq!(watcher.ini().get("a", "key").unwrap(), "3");
+
+        // Saving through the watcher is picked up without reporting any changes
+        let shared = watcher.shared();
+        let mut ini = shared.write().unwrap();
+        ini.set("a", "key", "4");
+        ini.save().unwrap();
+        drop(ini);
+        fs::write(&path, "[a]\nkey = 5\n[b]\nnew = 1\n").unwrap();
+        match wait() {
+            WatchEvent::Reloaded(x) => assert_eq!(x, [Change::KeyChanged { section: "a".to_string(), key: "key".to_string() }]),
+            x => panic!("expected a reload, got {:?}", x),
+        }
+
+        // A file that doesn't parse keeps the last good config
+        fs::write(&path, "[a\n").unwrap();
+        assert!(matches!(wait(), WatchEvent::Failed(_)));
+        assert_eq!(watcher.ini().get("a", "key").unwrap(), "5");
+    }
+
+    #[test]
+    fn test_watch() {
+        watch("watched.ini", false);
+    }
+
+    #[test]
+    fn test_watch_polling() {
+        watch("polled.ini", true);
+    }
+}